@@ -21,6 +21,151 @@ SET row_security = OFF;
 
 CREATE DOMAIN public.email AS public.citext CONSTRAINT email_check CHECK ((VALUE OPERATOR (public. ~) '^[a-zA-Z0-9.!#$%&''*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*$'::public.citext));
 
+CREATE TEXT SEARCH CONFIGURATION public.portuguese_unaccent (
+    PARSER = pg_catalog."default");
+
+ALTER TEXT SEARCH CONFIGURATION public.portuguese_unaccent
+    ADD MAPPING FOR asciiword, asciihword, hword_asciipart WITH pg_catalog.portuguese_stem;
+
+ALTER TEXT SEARCH CONFIGURATION public.portuguese_unaccent
+    ADD MAPPING FOR word, hword, hword_part WITH public.unaccent, pg_catalog.portuguese_stem;
+
+ALTER TEXT SEARCH CONFIGURATION public.portuguese_unaccent
+    ADD MAPPING FOR numword, numhword, email, url, host, file, url_path, version, sfloat, float, int, uint WITH simple;
+
+COMMENT ON TEXT SEARCH CONFIGURATION public.portuguese_unaccent IS 'Portuguese stemming, ignoring accents';
+
+CREATE TABLE public.api_keys (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    shop text NOT NULL,
+    name text NOT NULL,
+    scopes text[] NOT NULL,
+    prefix text NOT NULL,
+    key_hash text NOT NULL,
+    created_by public.citext,
+    created timestamp with time zone NOT NULL,
+    last_used timestamp with time zone,
+    CONSTRAINT api_keys_scopes_check CHECK ((scopes <@ ARRAY['read'::text, 'products:write'::text]))
+);
+
+COMMENT ON COLUMN public.api_keys.key_hash IS 'HMAC-SHA256 of the key, keyed by the server token_key';
+
+CREATE TABLE public.audit_log (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    "time" timestamp with time zone NOT NULL,
+    actor public.citext,
+    api_key uuid,
+    action text NOT NULL,
+    target_type text NOT NULL,
+    target text NOT NULL,
+    before jsonb,
+    after jsonb,
+    CONSTRAINT audit_log_action_check CHECK ((action = ANY (ARRAY['create'::text, 'update'::text, 'delete'::text, 'refund'::text, 'cancel'::text])))
+);
+
+COMMENT ON TABLE public.audit_log IS 'Append only, updates and deletes are ignored';
+
+COMMENT ON COLUMN public.audit_log.actor IS 'Not a foreign key, entries outlive the user';
+
+COMMENT ON COLUMN public.audit_log.api_key IS 'Set when the action was done by an integration';
+
+CREATE TABLE public.cart_items (
+    cart uuid NOT NULL,
+    product text NOT NULL,
+    amount integer NOT NULL,
+    price numeric NOT NULL,
+    variant uuid,
+    CONSTRAINT cart_items_amount_check CHECK ((amount > 0))
+);
+
+COMMENT ON COLUMN public.cart_items.price IS 'Unit price when the item was added, revalidated at checkout';
+
+COMMENT ON COLUMN public.cart_items.variant IS 'Required when the product has variants';
+
+CREATE TABLE public.carts (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    owner public.citext NOT NULL,
+    shop text
+);
+
+COMMENT ON COLUMN public.carts.shop IS 'Null for a cart across every shop';
+
+CREATE TABLE public.categories (
+    slug text NOT NULL,
+    name text NOT NULL,
+    parent text
+);
+
+COMMENT ON TABLE public.categories IS 'Site wide category tree, managed by admins';
+
+COMMENT ON COLUMN public.categories.parent IS 'Null for root categories';
+
+CREATE TABLE public.email_verifications (
+    token_hash text NOT NULL,
+    owner public.citext NOT NULL,
+    expires timestamp with time zone NOT NULL
+);
+
+COMMENT ON TABLE public.email_verifications IS 'Single use tokens, deleted when consumed';
+
+CREATE TABLE public.login_attempts (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    email public.citext NOT NULL,
+    ip text,
+    "time" timestamp with time zone NOT NULL,
+    success boolean NOT NULL
+);
+
+COMMENT ON COLUMN public.login_attempts.email IS 'Not a foreign key, attempts on unknown emails are recorded too';
+
+CREATE TABLE public.orders (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    purchaser public.citext,
+    status text NOT NULL,
+    total numeric NOT NULL,
+    "time" timestamp with time zone NOT NULL,
+    CONSTRAINT orders_status_check CHECK ((status = ANY (ARRAY['pending'::text, 'paid'::text, 'cancelled'::text])))
+);
+
+COMMENT ON COLUMN public.orders.purchaser IS 'Nullable to keep record even if user is deleted';
+
+CREATE TABLE public.password_resets (
+    token_hash text NOT NULL,
+    owner public.citext NOT NULL,
+    expires timestamp with time zone NOT NULL
+);
+
+COMMENT ON TABLE public.password_resets IS 'Single use tokens, deleted when consumed';
+
+CREATE TABLE public.product_categories (
+    product text NOT NULL,
+    category text NOT NULL
+);
+
+CREATE TABLE public.product_tags (
+    product text NOT NULL,
+    shop text NOT NULL,
+    tag text NOT NULL
+);
+
+COMMENT ON COLUMN public.product_tags.shop IS 'Shop of the tag, always the product''s shop';
+
+CREATE TABLE public.product_variants (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    product text NOT NULL,
+    sku text NOT NULL,
+    options jsonb DEFAULT '{}'::jsonb NOT NULL,
+    price numeric,
+    available integer NOT NULL,
+    sold integer NOT NULL
+);
+
+COMMENT ON TABLE public.product_variants IS 'Stock keeping units of a product, such as one size and color of a shirt';
+
+COMMENT ON COLUMN public.product_variants.options IS 'Value for each of the product''s option axes';
+
+COMMENT ON COLUMN public.product_variants.price IS 'Overrides the product price when set';
+
 CREATE TABLE public.products (
     slug text NOT NULL,
     shop text NOT NULL,
@@ -29,19 +174,49 @@ CREATE TABLE public.products (
     available integer NOT NULL,
     sold integer NOT NULL,
     details text NOT NULL,
-    picture text NOT NULL
+    picture text NOT NULL,
+    thumbnail text,
+    options text[] DEFAULT '{}'::text[] NOT NULL,
+    uploads text[] DEFAULT '{}'::text[] NOT NULL,
+    search tsvector GENERATED ALWAYS AS ((setweight(to_tsvector('public.portuguese_unaccent'::regconfig, name), 'A') || setweight(to_tsvector('public.portuguese_unaccent'::regconfig, details), 'B'))) STORED
 );
 
+COMMENT ON COLUMN public.products.options IS 'Option axes of the variants, such as size and color. Available and sold are the sums over the variants, when there are any';
+
+COMMENT ON COLUMN public.products.thumbnail IS 'Resized picture, only for uploaded pictures';
+
+COMMENT ON COLUMN public.products.uploads IS 'Storage keys of the files uploaded for this product, the only ones removed with it';
+
+COMMENT ON COLUMN public.products.search IS 'Full-text search document, name weighted above details';
+
 CREATE TABLE public.purchases (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
     product text,
     amount integer NOT NULL,
     paid numeric NOT NULL,
     purchaser public.citext,
-    "time" timestamp with time zone NOT NULL
+    "time" timestamp with time zone NOT NULL,
+    "order" uuid,
+    payment_status text DEFAULT 'pending'::text NOT NULL,
+    payment_reference text,
+    refunded integer DEFAULT 0 NOT NULL,
+    refund numeric DEFAULT 0 NOT NULL,
+    refund_reason text,
+    variant uuid,
+    CONSTRAINT purchases_refunded_check CHECK (((refunded >= 0) AND (refunded <= amount))),
+    CONSTRAINT purchases_payment_status_check CHECK ((payment_status = ANY (ARRAY['pending'::text, 'authorized'::text, 'captured'::text, 'refunded'::text, 'failed'::text])))
 );
 
 COMMENT ON COLUMN public.purchases.product IS 'Nullable to keep record even for deleted products';
 
+COMMENT ON COLUMN public.purchases.payment_reference IS 'Payment identifier at the payment provider';
+
+COMMENT ON COLUMN public.purchases.refunded IS 'Amount of units refunded and returned to stock';
+
+COMMENT ON COLUMN public.purchases.refund IS 'Total value refunded';
+
+COMMENT ON COLUMN public.purchases.variant IS 'Nullable to keep record even for deleted variants';
+
 COMMENT ON COLUMN public.purchases.purchaser IS 'Nullable to keep record even if user is deleted';
 
 CREATE SEQUENCE public.purchases_id_seq
@@ -51,48 +226,288 @@ CREATE SEQUENCE public.purchases_id_seq
     NO MAXVALUE
     CACHE 1;
 
+CREATE TABLE public.recovery_codes (
+    owner public.citext NOT NULL,
+    code_hash text NOT NULL
+);
+
+COMMENT ON TABLE public.recovery_codes IS 'Single use two-factor recovery codes, deleted when consumed';
+
+COMMENT ON COLUMN public.recovery_codes.code_hash IS 'HMAC-SHA256 of the code, keyed by the server token_key';
+
+CREATE TABLE public.sessions (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    owner public.citext NOT NULL,
+    token_hash text NOT NULL,
+    created timestamp with time zone NOT NULL,
+    last_seen timestamp with time zone NOT NULL,
+    expires timestamp with time zone NOT NULL,
+    user_agent text,
+    ip text
+);
+
+COMMENT ON COLUMN public.sessions.token_hash IS 'HMAC-SHA256 of the token, keyed by the server token_key';
+
+CREATE TABLE public.settings (
+    name text NOT NULL,
+    value jsonb NOT NULL
+);
+
+COMMENT ON TABLE public.settings IS 'Site wide settings, editable by admins';
+
+CREATE TABLE public.shop_members (
+    shop text NOT NULL,
+    member public.citext NOT NULL,
+    role text NOT NULL,
+    CONSTRAINT shop_members_role_check CHECK ((role = ANY (ARRAY['owner'::text, 'editor'::text, 'viewer'::text])))
+);
+
+COMMENT ON TABLE public.shop_members IS 'Staff besides the shop manager, who is always an owner';
+
 CREATE TABLE public.shops (
     slug text NOT NULL,
     name text NOT NULL,
     color_dark character varying(6) NOT NULL,
     color_light character varying(6) NOT NULL,
     manager public.citext NOT NULL,
-    logo text NOT NULL
+    logo text NOT NULL,
+    logo_thumbnail text,
+    uploads text[] DEFAULT '{}'::text[] NOT NULL
 );
 
 COMMENT ON COLUMN public.shops.slug IS 'Shop slug name';
 
+COMMENT ON COLUMN public.shops.logo_thumbnail IS 'Resized logo, only for uploaded logos';
+
+COMMENT ON COLUMN public.shops.uploads IS 'Storage keys of the files uploaded for this shop, the only ones removed with it';
+
+CREATE TABLE public.tags (
+    shop text NOT NULL,
+    slug text NOT NULL,
+    name text NOT NULL
+);
+
+COMMENT ON TABLE public.tags IS 'Free-form product labels, scoped to a shop';
+
+CREATE TABLE public.two_factor (
+    owner public.citext NOT NULL,
+    secret text NOT NULL,
+    enabled boolean DEFAULT false NOT NULL,
+    last_step bigint
+);
+
+COMMENT ON COLUMN public.two_factor.enabled IS 'False while the enrollment is not confirmed';
+
+COMMENT ON COLUMN public.two_factor.last_step IS 'Last accepted TOTP time step, codes can not be reused';
+
+CREATE TABLE public.user_identities (
+    issuer text NOT NULL,
+    subject text NOT NULL,
+    owner public.citext NOT NULL,
+    created timestamp with time zone NOT NULL
+);
+
+COMMENT ON TABLE public.user_identities IS 'Accounts at OpenID Connect providers linked to users';
+
 CREATE TABLE public.users (
     email public.citext NOT NULL,
     password text NOT NULL,
     name text NOT NULL,
     admin boolean NOT NULL,
-    token text DEFAULT 'NULL' ::text
+    verified boolean DEFAULT false NOT NULL,
+    cpf text
 );
 
 COMMENT ON COLUMN public.users.email IS 'User email';
 
+COMMENT ON COLUMN public.users.cpf IS 'CPF or CNPJ, digits only';
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_key_hash_key UNIQUE (key_hash);
+
+ALTER TABLE ONLY public.audit_log
+    ADD CONSTRAINT audit_log_pkey PRIMARY KEY (id);
+
+CREATE INDEX audit_log_time_idx ON public.audit_log USING btree ("time");
+
+CREATE INDEX audit_log_actor_idx ON public.audit_log USING btree (actor, "time");
+
+CREATE INDEX audit_log_target_idx ON public.audit_log USING btree (target_type, target, "time");
+
+CREATE RULE audit_log_no_update AS ON UPDATE TO public.audit_log DO INSTEAD NOTHING;
+
+CREATE RULE audit_log_no_delete AS ON DELETE TO public.audit_log DO INSTEAD NOTHING;
+
+CREATE UNIQUE INDEX cart_items_cart_product_variant_key ON public.cart_items USING btree (cart, product, (COALESCE(variant, '00000000-0000-0000-0000-000000000000'::uuid)));
+
+ALTER TABLE ONLY public.carts
+    ADD CONSTRAINT carts_pkey PRIMARY KEY (id);
+
+CREATE UNIQUE INDEX carts_owner_shop_key ON public.carts USING btree (owner, (COALESCE(shop, ''::text)));
+
+ALTER TABLE ONLY public.categories
+    ADD CONSTRAINT categories_pkey PRIMARY KEY (slug);
+
+CREATE INDEX categories_parent_idx ON public.categories USING btree (parent);
+
+ALTER TABLE ONLY public.email_verifications
+    ADD CONSTRAINT email_verifications_pkey PRIMARY KEY (token_hash);
+
+ALTER TABLE ONLY public.login_attempts
+    ADD CONSTRAINT login_attempts_pkey PRIMARY KEY (id);
+
+CREATE INDEX login_attempts_email_idx ON public.login_attempts USING btree (email, "time");
+
+CREATE INDEX login_attempts_ip_idx ON public.login_attempts USING btree (ip, "time");
+
+ALTER TABLE ONLY public.orders
+    ADD CONSTRAINT orders_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.password_resets
+    ADD CONSTRAINT password_resets_pkey PRIMARY KEY (token_hash);
+
+ALTER TABLE ONLY public.product_categories
+    ADD CONSTRAINT product_categories_pkey PRIMARY KEY (product, category);
+
+CREATE INDEX product_categories_category_idx ON public.product_categories USING btree (category);
+
+ALTER TABLE ONLY public.product_tags
+    ADD CONSTRAINT product_tags_pkey PRIMARY KEY (product, shop, tag);
+
+CREATE INDEX product_tags_tag_idx ON public.product_tags USING btree (shop, tag);
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_sku_key UNIQUE (sku);
+
+CREATE UNIQUE INDEX product_variants_product_options_key ON public.product_variants USING btree (product, options);
+
 ALTER TABLE ONLY public.products
     ADD CONSTRAINT products_pkey PRIMARY KEY (slug);
 
+CREATE INDEX products_search_idx ON public.products USING gin (search);
+
 ALTER TABLE ONLY public.purchases
-    ADD CONSTRAINT purchases_time PRIMARY KEY ("time");
+    ADD CONSTRAINT purchases_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.recovery_codes
+    ADD CONSTRAINT recovery_codes_pkey PRIMARY KEY (owner, code_hash);
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_token_hash_key UNIQUE (token_hash);
+
+ALTER TABLE ONLY public.settings
+    ADD CONSTRAINT settings_pkey PRIMARY KEY (name);
+
+ALTER TABLE ONLY public.shop_members
+    ADD CONSTRAINT shop_members_pkey PRIMARY KEY (shop, member);
 
 ALTER TABLE ONLY public.shops
     ADD CONSTRAINT shop_pkey PRIMARY KEY (slug);
 
+ALTER TABLE ONLY public.tags
+    ADD CONSTRAINT tags_pkey PRIMARY KEY (shop, slug);
+
+ALTER TABLE ONLY public.two_factor
+    ADD CONSTRAINT two_factor_pkey PRIMARY KEY (owner);
+
+ALTER TABLE ONLY public.user_identities
+    ADD CONSTRAINT user_identities_pkey PRIMARY KEY (issuer, subject);
+
 ALTER TABLE ONLY public.users
     ADD CONSTRAINT users_pkey PRIMARY KEY (email);
 
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_shop_fkey FOREIGN KEY (shop) REFERENCES public.shops (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_created_by_fkey FOREIGN KEY (created_by) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE SET NULL;
+
+ALTER TABLE ONLY public.cart_items
+    ADD CONSTRAINT cart_items_cart_fkey FOREIGN KEY (cart) REFERENCES public.carts (id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.cart_items
+    ADD CONSTRAINT cart_items_product_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.cart_items
+    ADD CONSTRAINT cart_items_variant_fkey FOREIGN KEY (variant) REFERENCES public.product_variants (id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.carts
+    ADD CONSTRAINT carts_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.carts
+    ADD CONSTRAINT carts_shop_fkey FOREIGN KEY (shop) REFERENCES public.shops (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.categories
+    ADD CONSTRAINT categories_parent_fkey FOREIGN KEY (parent) REFERENCES public.categories (slug) ON UPDATE CASCADE ON DELETE RESTRICT;
+
+ALTER TABLE ONLY public.email_verifications
+    ADD CONSTRAINT email_verifications_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.orders
+    ADD CONSTRAINT orders_purchaser_fkey FOREIGN KEY (purchaser) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE SET NULL;
+
+ALTER TABLE ONLY public.password_resets
+    ADD CONSTRAINT password_resets_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.product_categories
+    ADD CONSTRAINT product_categories_product_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.product_categories
+    ADD CONSTRAINT product_categories_category_fkey FOREIGN KEY (category) REFERENCES public.categories (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.product_tags
+    ADD CONSTRAINT product_tags_product_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.product_tags
+    ADD CONSTRAINT product_tags_tag_fkey FOREIGN KEY (shop, tag) REFERENCES public.tags (shop, slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_product_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
 ALTER TABLE ONLY public.products
     ADD CONSTRAINT products_shop_slug_fkey FOREIGN KEY (shop) REFERENCES public.shops (slug) ON UPDATE CASCADE ON DELETE CASCADE;
 
 ALTER TABLE ONLY public.purchases
     ADD CONSTRAINT sales_product_slug_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE SET NULL;
 
+ALTER TABLE ONLY public.purchases
+    ADD CONSTRAINT purchases_order_fkey FOREIGN KEY ("order") REFERENCES public.orders (id) ON DELETE SET NULL;
+
+ALTER TABLE ONLY public.purchases
+    ADD CONSTRAINT purchases_variant_fkey FOREIGN KEY (variant) REFERENCES public.product_variants (id) ON DELETE SET NULL;
+
 ALTER TABLE ONLY public.purchases
     ADD CONSTRAINT sales_purchaser_email_fkey FOREIGN KEY (purchaser) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE SET NULL;
 
+ALTER TABLE ONLY public.recovery_codes
+    ADD CONSTRAINT recovery_codes_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.shop_members
+    ADD CONSTRAINT shop_members_shop_fkey FOREIGN KEY (shop) REFERENCES public.shops (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.shop_members
+    ADD CONSTRAINT shop_members_member_fkey FOREIGN KEY (member) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
 ALTER TABLE ONLY public.shops
     ADD CONSTRAINT shops_owner_email_fkey FOREIGN KEY (manager) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
 
+ALTER TABLE ONLY public.tags
+    ADD CONSTRAINT tags_shop_fkey FOREIGN KEY (shop) REFERENCES public.shops (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.two_factor
+    ADD CONSTRAINT two_factor_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.user_identities
+    ADD CONSTRAINT user_identities_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
@@ -0,0 +1,36 @@
+-- Migra uma database anterior às sessões múltiplas: cada token de `users.token` vira uma sessão,
+-- com a validade normal de 30 dias, e a coluna é removida. Ninguém é deslogado.
+--
+-- psql -v ON_ERROR_STOP=1 -d <database> -f migrations/01-sessions.sql
+
+BEGIN;
+
+CREATE TABLE public.sessions (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    owner public.citext NOT NULL,
+    token text NOT NULL,
+    created timestamp with time zone NOT NULL,
+    last_seen timestamp with time zone NOT NULL,
+    expires timestamp with time zone NOT NULL,
+    user_agent text,
+    ip text
+);
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_token_key UNIQUE (token);
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_owner_fkey FOREIGN KEY (owner) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+-- O valor padrão antigo da coluna era o texto 'NULL', e não um NULL de verdade
+INSERT INTO public.sessions (owner, token, created, last_seen, expires)
+SELECT email, token, now(), now(), now() + interval '30 days'
+FROM public.users
+WHERE token IS NOT NULL AND token <> 'NULL' AND token <> '';
+
+ALTER TABLE public.users DROP COLUMN token;
+
+COMMIT;
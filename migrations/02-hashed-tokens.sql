@@ -0,0 +1,38 @@
+-- Guarda apenas o hash (HMAC-SHA256, com a chave `token_key` do servidor) dos tokens de sessões,
+-- de redefinição de senha e de confirmação de email, e remove os tokens em texto puro. Os tokens
+-- existentes continuam válidos. A chave deve ser exatamente a mesma configurada no servidor:
+--
+-- psql -v ON_ERROR_STOP=1 -v token_key="$ROCKET_TOKEN_KEY" -d <database> -f migrations/02-hashed-tokens.sql
+
+BEGIN;
+
+CREATE EXTENSION IF NOT EXISTS pgcrypto WITH SCHEMA public;
+
+ALTER TABLE public.sessions ADD COLUMN IF NOT EXISTS token_hash text;
+
+UPDATE public.sessions
+SET token_hash = encode(public.hmac(token, :'token_key', 'sha256'), 'hex')
+WHERE token IS NOT NULL;
+
+ALTER TABLE public.sessions DROP COLUMN token;
+
+ALTER TABLE public.sessions ALTER COLUMN token_hash SET NOT NULL;
+
+ALTER TABLE public.sessions DROP CONSTRAINT IF EXISTS sessions_token_hash_key;
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_token_hash_key UNIQUE (token_hash);
+
+COMMENT ON COLUMN public.sessions.token_hash IS 'HMAC-SHA256 of the token, keyed by the server token_key';
+
+ALTER TABLE public.password_resets RENAME COLUMN token TO token_hash;
+
+UPDATE public.password_resets
+SET token_hash = encode(public.hmac(token_hash, :'token_key', 'sha256'), 'hex');
+
+ALTER TABLE public.email_verifications RENAME COLUMN token TO token_hash;
+
+UPDATE public.email_verifications
+SET token_hash = encode(public.hmac(token_hash, :'token_key', 'sha256'), 'hex');
+
+COMMIT;
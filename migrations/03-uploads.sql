@@ -0,0 +1,25 @@
+-- Adiciona as miniaturas das fotos dos produtos e dos logos das lojas, e guarda, em cada produto e
+-- loja, as chaves no armazenamento dos arquivos enviados para eles, que passam a ser os únicos
+-- removidos junto com a linha. As linhas existentes começam sem miniaturas e sem chaves:
+--
+-- psql -v ON_ERROR_STOP=1 -d <database> -f migrations/03-uploads.sql
+
+BEGIN;
+
+ALTER TABLE public.products ADD COLUMN thumbnail text;
+
+COMMENT ON COLUMN public.products.thumbnail IS 'Resized picture, only for uploaded pictures';
+
+ALTER TABLE public.products ADD COLUMN uploads text[] DEFAULT '{}'::text[] NOT NULL;
+
+COMMENT ON COLUMN public.products.uploads IS 'Storage keys of the files uploaded for this product, the only ones removed with it';
+
+ALTER TABLE public.shops ADD COLUMN logo_thumbnail text;
+
+COMMENT ON COLUMN public.shops.logo_thumbnail IS 'Resized logo, only for uploaded logos';
+
+ALTER TABLE public.shops ADD COLUMN uploads text[] DEFAULT '{}'::text[] NOT NULL;
+
+COMMENT ON COLUMN public.shops.uploads IS 'Storage keys of the files uploaded for this shop, the only ones removed with it';
+
+COMMIT;
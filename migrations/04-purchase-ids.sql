@@ -0,0 +1,95 @@
+-- Identifica as compras por um UUID gerado, em vez do horário, e adiciona as colunas dos pedidos,
+-- dos pagamentos e dos reembolsos (criando as tabelas de pedidos e de variantes, às quais as
+-- compras se referem). As compras existentes ganham um id cada e ficam como pagas, sem
+-- referência no provedor, então reembolsá-las apenas devolve as unidades ao estoque:
+--
+-- psql -v ON_ERROR_STOP=1 -d <database> -f migrations/04-purchase-ids.sql
+
+BEGIN;
+
+CREATE TABLE public.orders (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    purchaser public.citext,
+    status text NOT NULL,
+    total numeric NOT NULL,
+    "time" timestamp with time zone NOT NULL,
+    CONSTRAINT orders_status_check CHECK ((status = ANY (ARRAY['pending'::text, 'paid'::text, 'cancelled'::text])))
+);
+
+COMMENT ON COLUMN public.orders.purchaser IS 'Nullable to keep record even if user is deleted';
+
+ALTER TABLE ONLY public.orders
+    ADD CONSTRAINT orders_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.orders
+    ADD CONSTRAINT orders_purchaser_fkey FOREIGN KEY (purchaser) REFERENCES public.users (email) ON UPDATE CASCADE ON DELETE SET NULL;
+
+CREATE TABLE public.product_variants (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    product text NOT NULL,
+    sku text NOT NULL,
+    options jsonb DEFAULT '{}'::jsonb NOT NULL,
+    price numeric,
+    available integer NOT NULL,
+    sold integer NOT NULL
+);
+
+COMMENT ON TABLE public.product_variants IS 'Stock keeping units of a product, such as one size and color of a shirt';
+
+COMMENT ON COLUMN public.product_variants.options IS 'Value for each of the product''s option axes';
+
+COMMENT ON COLUMN public.product_variants.price IS 'Overrides the product price when set';
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_sku_key UNIQUE (sku);
+
+CREATE UNIQUE INDEX product_variants_product_options_key ON public.product_variants USING btree (product, options);
+
+ALTER TABLE ONLY public.product_variants
+    ADD CONSTRAINT product_variants_product_fkey FOREIGN KEY (product) REFERENCES public.products (slug) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE public.purchases ADD COLUMN id uuid;
+
+UPDATE public.purchases SET id = public.uuid_generate_v4();
+
+ALTER TABLE public.purchases ALTER COLUMN id SET DEFAULT public.uuid_generate_v4();
+
+ALTER TABLE public.purchases ALTER COLUMN id SET NOT NULL;
+
+ALTER TABLE public.purchases DROP CONSTRAINT purchases_time;
+
+ALTER TABLE ONLY public.purchases
+    ADD CONSTRAINT purchases_pkey PRIMARY KEY (id);
+
+ALTER TABLE public.purchases
+    ADD COLUMN "order" uuid,
+    ADD COLUMN payment_status text DEFAULT 'pending'::text NOT NULL,
+    ADD COLUMN payment_reference text,
+    ADD COLUMN refunded integer DEFAULT 0 NOT NULL,
+    ADD COLUMN refund numeric DEFAULT 0 NOT NULL,
+    ADD COLUMN refund_reason text,
+    ADD COLUMN variant uuid,
+    ADD CONSTRAINT purchases_refunded_check CHECK (((refunded >= 0) AND (refunded <= amount))),
+    ADD CONSTRAINT purchases_payment_status_check CHECK ((payment_status = ANY (ARRAY['pending'::text, 'authorized'::text, 'captured'::text, 'refunded'::text, 'failed'::text])));
+
+-- As compras anteriores aos pagamentos já estavam concluídas
+UPDATE public.purchases SET payment_status = 'captured';
+
+COMMENT ON COLUMN public.purchases.payment_reference IS 'Payment identifier at the payment provider';
+
+COMMENT ON COLUMN public.purchases.refunded IS 'Amount of units refunded and returned to stock';
+
+COMMENT ON COLUMN public.purchases.refund IS 'Total value refunded';
+
+COMMENT ON COLUMN public.purchases.variant IS 'Nullable to keep record even for deleted variants';
+
+ALTER TABLE ONLY public.purchases
+    ADD CONSTRAINT purchases_order_fkey FOREIGN KEY ("order") REFERENCES public.orders (id) ON DELETE SET NULL;
+
+ALTER TABLE ONLY public.purchases
+    ADD CONSTRAINT purchases_variant_fkey FOREIGN KEY (variant) REFERENCES public.product_variants (id) ON DELETE SET NULL;
+
+COMMIT;
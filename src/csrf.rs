@@ -0,0 +1,75 @@
+use crate::{Error, Result};
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rocket::http::{Cookie, SameSite, Status};
+use rocket::request::{self, FromRequest, Request};
+
+/// Nome do cookie privado que guarda o token anti-CSRF
+pub const COOKIE: &str = "csrf";
+/// Header pelo qual scripts podem enviar o token anti-CSRF
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Token anti-CSRF da sessão do navegador.
+///
+/// Fica guardado num cookie privado e deve ser repetido em cada formulário (campo `csrf`) ou
+/// request que altera estado (header `X-CSRF-Token`), provando que ele partiu das nossas páginas.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    /// Verifica se o token enviado bate com o da sessão
+    pub fn verify(&self, submitted: &str) -> Result<()> {
+        if constant_time_eq(self.0.as_bytes(), submitted.as_bytes()) {
+            Ok(())
+        } else {
+            Err(Error::builder()
+                .code(Status::Forbidden)
+                .description("Token anti-CSRF inválido, recarregue a página e tente novamente")
+                .build())
+        }
+    }
+    /// Verifica o token enviado pelo header, para requests que não vêm de formulários
+    pub fn verify_header(req: &Request<'_>) -> Result<()> {
+        let token = req.cookies().get_private(COOKIE).ok_or_else(|| {
+            Error::builder()
+                .code(Status::Forbidden)
+                .description("Sessão sem token anti-CSRF")
+                .build()
+        })?;
+        let submitted = req.headers().get_one(HEADER).ok_or_else(|| {
+            Error::builder()
+                .code(Status::Forbidden)
+                .description(&format!("Esse request deve conter o header '{}'", HEADER))
+                .build()
+        })?;
+        CsrfToken(token.value().into()).verify(submitted)
+    }
+}
+
+/// Compara sem vazar, pelo tempo gasto, quantos bytes coincidem
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfToken {
+    type Error = Error;
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Error> {
+        let cookies = req.cookies();
+        if let Some(cookie) = cookies.get_private(COOKIE) {
+            return request::Outcome::Success(CsrfToken(cookie.value().into()));
+        }
+        // Primeira visita: gerar um token novo
+        let token: String = thread_rng().sample_iter(Alphanumeric).take(32).collect();
+        cookies.add_private(
+            Cookie::build(COOKIE, token.clone())
+                .secure(true)
+                .same_site(SameSite::Strict)
+                .finish(),
+        );
+        request::Outcome::Success(CsrfToken(token))
+    }
+}
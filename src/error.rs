@@ -67,6 +67,16 @@ impl Error {
         let error = source.into();
         error.edit()
     }
+    pub fn code(&self) -> Status {
+        self.code
+    }
+    /// Mensagem legível para o usuário: a descrição, ou o motivo do status caso não tenha
+    pub fn message(&self) -> String {
+        match &self.description {
+            Some(description) => description.clone(),
+            None => self.code.reason_lossy().into(),
+        }
+    }
 }
 
 impl Default for Error {
@@ -177,3 +187,49 @@ impl From<rocket::serde::json::Error<'_>> for Error {
             .build()
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::builder()
+            .source(Box::new(e))
+            .description("Erro de entrada/saída")
+            .build()
+    }
+}
+
+impl From<lettre::address::AddressError> for Error {
+    fn from(e: lettre::address::AddressError) -> Self {
+        Error::builder()
+            .source(Box::new(e))
+            .description("Endereço de email inválido")
+            .build()
+    }
+}
+
+impl From<lettre::error::Error> for Error {
+    fn from(e: lettre::error::Error) -> Self {
+        Error::builder()
+            .source(Box::new(e))
+            .description("Não foi possível montar o email")
+            .build()
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        Error::builder()
+            .code(Status::ServiceUnavailable)
+            .source(Box::new(e))
+            .description("Não foi possível enviar o email")
+            .build()
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::builder()
+            .source(Box::new(e))
+            .description("Não foi possível converter o JSON")
+            .build()
+    }
+}
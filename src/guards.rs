@@ -0,0 +1,249 @@
+//! Request guards de autorização. Cada handler declara na assinatura quem pode acessá-lo; em
+//! caso de falha, o guard produz o mesmo `Error` JSON que as rotas retornam.
+use crate::schema::{Actor, ApiKey, Permission, Shop, User, UserToken};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use rocket::http::Status;
+use rocket::outcome::{try_outcome, Outcome};
+use rocket::request::{self, FromRequest, Request};
+use rocket::State;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+fn failure<T>(error: Error) -> request::Outcome<T, Error> {
+    Outcome::Failure((error.code(), error))
+}
+
+/// Database obtida de dentro de um guard
+async fn database(req: &Request<'_>) -> request::Outcome<Database, Error> {
+    req.guard::<Database>().await.map_failure(|(status, ())| {
+        (
+            status,
+            Error::builder()
+                .code(status)
+                .description("Não foi possível conectar à database")
+                .build(),
+        )
+    })
+}
+
+/// Chave de tokens gerenciada, obtida de dentro de um guard
+async fn token_key<'r>(req: &'r Request<'_>) -> request::Outcome<&'r TokenKey, Error> {
+    req.guard::<&State<TokenKey>>()
+        .await
+        .map(|key| key.inner())
+        .map_failure(|(status, ())| {
+            (
+                status,
+                Error::builder()
+                    .code(status)
+                    .description("Chave de tokens não configurada")
+                    .build(),
+            )
+        })
+}
+
+/// Usuário dono de uma sessão válida
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub User);
+
+impl Deref for AuthenticatedUser {
+    type Target = User;
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl From<&AuthenticatedUser> for Actor {
+    fn from(user: &AuthenticatedUser) -> Actor {
+        Actor::from(&user.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = Error;
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Error> {
+        let token = try_outcome!(req.guard::<UserToken>().await);
+        let db = try_outcome!(database(req).await);
+        let key = try_outcome!(token_key(req).await);
+        match User::read_from_token(&db, key, &token).await {
+            Ok(user) => Outcome::Success(AuthenticatedUser(user)),
+            Err(e) => failure(e),
+        }
+    }
+}
+
+/// Usuário autenticado e administrador
+#[derive(Clone, Debug)]
+pub struct AdminUser(pub User);
+
+impl Deref for AdminUser {
+    type Target = User;
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl From<&AdminUser> for Actor {
+    fn from(user: &AdminUser) -> Actor {
+        Actor::from(&user.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = Error;
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Error> {
+        let AuthenticatedUser(user) = try_outcome!(req.guard::<AuthenticatedUser>().await);
+        if user.admin {
+            Outcome::Success(AdminUser(user))
+        } else {
+            failure(
+                Error::builder()
+                    .code(Status::Forbidden)
+                    .description("Apenas administradores podem fazer isso")
+                    .build(),
+            )
+        }
+    }
+}
+
+/// Permissão exigida por um `ShopManager`, como um tipo
+pub trait ShopPermission: Send + Sync + 'static {
+    const PERMISSION: Permission;
+}
+
+/// Exige `Permission::EditShop`
+#[derive(Clone, Copy, Debug)]
+pub struct EditShop;
+
+impl ShopPermission for EditShop {
+    const PERMISSION: Permission = Permission::EditShop;
+}
+
+/// Exige `Permission::DeleteShop`
+#[derive(Clone, Copy, Debug)]
+pub struct DeleteShop;
+
+impl ShopPermission for DeleteShop {
+    const PERMISSION: Permission = Permission::DeleteShop;
+}
+
+/// Exige `Permission::ManageMembers`
+#[derive(Clone, Copy, Debug)]
+pub struct ManageMembers;
+
+impl ShopPermission for ManageMembers {
+    const PERMISSION: Permission = Permission::ManageMembers;
+}
+
+/// Exige `Permission::ManageApiKeys`
+#[derive(Clone, Copy, Debug)]
+pub struct ManageApiKeys;
+
+impl ShopPermission for ManageApiKeys {
+    const PERMISSION: Permission = Permission::ManageApiKeys;
+}
+
+/// Membro da equipe (ou administrador) com a permissão `P` na loja cujo slug é o primeiro
+/// segmento da rota, como em `/shops/<slug>`
+#[derive(Clone, Debug)]
+pub struct ShopManager<P: ShopPermission> {
+    pub user: User,
+    pub shop: Shop,
+    permission: PhantomData<P>,
+}
+
+impl<P: ShopPermission> From<&ShopManager<P>> for Actor {
+    fn from(manager: &ShopManager<P>) -> Actor {
+        Actor::from(&manager.user)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, P: ShopPermission> FromRequest<'r> for ShopManager<P> {
+    type Error = Error;
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Error> {
+        let AuthenticatedUser(user) = try_outcome!(req.guard::<AuthenticatedUser>().await);
+        let slug = match req.param::<String>(0) {
+            Some(Ok(slug)) => slug,
+            _ => {
+                return failure(
+                    Error::builder()
+                        .code(Status::NotFound)
+                        .description("Loja não encontrada")
+                        .build(),
+                )
+            }
+        };
+        let db = try_outcome!(database(req).await);
+        let shop = match Shop::read(&db, &slug).await {
+            Ok(shop) => shop,
+            Err(e) => return failure(e),
+        };
+        match shop.check_permission(&db, &user, P::PERMISSION).await {
+            Ok(()) => Outcome::Success(ShopManager {
+                user,
+                shop,
+                permission: PhantomData,
+            }),
+            Err(e) => failure(e),
+        }
+    }
+}
+
+/// Header pelo qual integrações mandam a chave de API
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Quem faz o request: um usuário autenticado ou uma integração, por chave de API
+#[derive(Clone, Debug)]
+pub enum Principal {
+    User(User),
+    ApiKey(ApiKey),
+}
+
+impl Principal {
+    /// Retorna erro caso não tenha a permissão na loja
+    pub async fn check_permission(
+        &self,
+        db: &Database,
+        shop: &Shop,
+        permission: Permission,
+    ) -> Result<()> {
+        match self {
+            Principal::User(user) => shop.check_permission(db, user, permission).await,
+            Principal::ApiKey(key) => key.check_permission(shop, permission),
+        }
+    }
+}
+
+impl From<&Principal> for Actor {
+    fn from(principal: &Principal) -> Actor {
+        match principal {
+            Principal::User(user) => Actor::from(user),
+            Principal::ApiKey(key) => Actor::from(key),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal {
+    type Error = Error;
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Error> {
+        let raw = match req.headers().get_one(API_KEY_HEADER) {
+            Some(raw) => raw,
+            None => {
+                let AuthenticatedUser(user) = try_outcome!(req.guard::<AuthenticatedUser>().await);
+                return Outcome::Success(Principal::User(user));
+            }
+        };
+        let db = try_outcome!(database(req).await);
+        let key = try_outcome!(token_key(req).await);
+        match ApiKey::read_from_key(&db, key, raw).await {
+            Ok(api_key) => Outcome::Success(Principal::ApiKey(api_key)),
+            Err(e) => failure(e),
+        }
+    }
+}
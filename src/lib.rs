@@ -1,8 +1,17 @@
 pub mod error;
 pub use error::{Error, Result};
 
+pub mod csrf;
+pub mod guards;
+pub mod mail;
+pub mod oidc;
+pub mod pages;
+pub mod payment;
 pub mod routes;
 pub mod schema;
+pub mod storage;
+pub mod token;
+pub mod totp;
 
 use rocket_sync_db_pools::database;
 #[database("database")]
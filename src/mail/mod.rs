@@ -0,0 +1,86 @@
+pub mod outbox;
+pub use outbox::OutboxMailer;
+
+pub mod smtp;
+pub use smtp::SmtpMailer;
+
+use crate::Result;
+
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Um email a ser enviado
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Um meio de envio de emails
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<()>;
+}
+
+/// Meio de envio de emails em uso, gerenciado pelo rocket
+pub type Mailers = Box<dyn Mailer>;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    transport: String,
+    from: Option<String>,
+    /// Arquivo do outbox `file`
+    path: Option<PathBuf>,
+    host: Option<String>,
+    port: Option<u16>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// Lê a seção `mail` da configuração e gerencia o meio de envio escolhido
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Mailer", |rocket| async {
+        let config: Config = match rocket.figment().extract_inner("mail") {
+            Ok(config) => config,
+            Err(e) => {
+                rocket::error!("Configuração de email inválida: {}", e);
+                return Err(rocket);
+            }
+        };
+        let mailer: Mailers = match config.transport.as_str() {
+            "stdout" => Box::new(OutboxMailer::stdout()),
+            "file" => match config.path {
+                Some(path) => Box::new(OutboxMailer::file(path)),
+                None => {
+                    rocket::error!("O outbox 'file' precisa de um 'path'");
+                    return Err(rocket);
+                }
+            },
+            "smtp" => {
+                let mailer = SmtpMailer::new(
+                    config.host.as_deref().unwrap_or("localhost"),
+                    config.port,
+                    config.username.zip(config.password),
+                    config
+                        .from
+                        .as_deref()
+                        .unwrap_or("noreply@cincobola.misterio.me"),
+                );
+                match mailer {
+                    Ok(mailer) => Box::new(mailer),
+                    Err(e) => {
+                        rocket::error!("Configuração de SMTP inválida: {}", e);
+                        return Err(rocket);
+                    }
+                }
+            }
+            other => {
+                rocket::error!("Meio de envio de email desconhecido: '{}'", other);
+                return Err(rocket);
+            }
+        };
+        Ok(rocket.manage(mailer))
+    })
+}
@@ -0,0 +1,90 @@
+use crate::mail::{Mail, Mailer};
+use crate::Result;
+
+use chrono::Utc;
+use rocket::tokio::fs::OpenOptions;
+use rocket::tokio::io::AsyncWriteExt;
+use std::path::PathBuf;
+
+/// "Envia" emails escrevendo-os num arquivo ou na saída padrão, para desenvolvimento e testes
+#[derive(Debug, Clone, Default)]
+pub struct OutboxMailer {
+    /// Arquivo onde os emails são adicionados, ou nenhum para a saída padrão
+    path: Option<PathBuf>,
+}
+
+impl OutboxMailer {
+    pub fn stdout() -> OutboxMailer {
+        OutboxMailer { path: None }
+    }
+    pub fn file(path: PathBuf) -> OutboxMailer {
+        OutboxMailer { path: Some(path) }
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        let text = format!(
+            "Date: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+            Utc::now().to_rfc2822(),
+            mail.to,
+            mail.subject,
+            mail.body
+        );
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(text.as_bytes()).await?;
+                // O tokio escreve em segundo plano, e o que não foi escrito se perde ao fechar
+                file.flush().await?;
+            }
+            None => print!("{}", text),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn mail(subject: &str) -> Mail {
+        Mail {
+            to: "fulano@exemplo.com".into(),
+            subject: subject.into(),
+            body: "Olá!".into(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn appends_mails_to_the_file() {
+        let path = std::env::temp_dir().join(format!("outbox-{}.txt", Uuid::new_v4()));
+        let mailer = OutboxMailer::file(path.clone());
+        mailer.send(mail("Primeiro")).await.unwrap();
+        mailer.send(mail("Segundo")).await.unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.matches("To: fulano@exemplo.com\n").count(), 2);
+        let first = text.find("Subject: Primeiro\n\nOlá!\n\n").unwrap();
+        let second = text.find("Subject: Segundo\n\nOlá!\n\n").unwrap();
+        assert!(first < second);
+    }
+
+    #[rocket::async_test]
+    async fn fails_on_unwritable_paths() {
+        let path = std::env::temp_dir()
+            .join(format!("outbox-{}", Uuid::new_v4()))
+            .join("outbox.txt");
+        assert!(OutboxMailer::file(path)
+            .send(mail("Assunto"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::mail::{Mail, Mailer};
+use crate::{Error, Result};
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::http::Status;
+
+/// Envia emails por um servidor SMTP (com STARTTLS)
+#[derive(Clone)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<SmtpMailer> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?;
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from: from.parse()?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Endereço de email inválido")
+            })?)
+            .subject(mail.subject)
+            .body(mail.body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
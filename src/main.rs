@@ -1,4 +1,4 @@
-use cincobola_backend::{routes, Database, Result};
+use cincobola_backend::{mail, oidc, pages, payment, routes, storage, token, Database, Result};
 
 use std::collections::HashMap;
 
@@ -17,13 +17,30 @@ async fn main() -> Result<()> {
     rocket::build()
         .attach(Template::fairing())
         .attach(Database::fairing())
+        .attach(payment::fairing())
+        .attach(mail::fairing())
+        .attach(token::fairing())
+        .attach(oidc::fairing())
+        .attach(storage::fairing())
         .mount("/static", FileServer::from(relative!("static")))
         .mount("/", routes![home])
+        .mount("/", pages::storefront::routes())
+        .mount("/", pages::session::routes())
         .mount("/session", routes::session::routes())
+        .mount("/oidc", routes::oidc::routes())
         .mount("/users", routes::users::routes())
+        .mount("/settings", routes::settings::routes())
+        .mount("/audit", routes::audit::routes())
         .mount("/shops", routes::shops::routes())
+        .mount("/shops", routes::members::routes())
+        .mount("/shops", routes::api_keys::routes())
+        .mount("/shops", routes::tags::routes())
+        .mount("/categories", routes::categories::routes())
         .mount("/products", routes::products::routes())
+        .mount("/products", routes::variants::routes())
         .mount("/purchases", routes::purchases::routes())
+        .mount("/orders", routes::orders::routes())
+        .mount("/carts", routes::carts::routes())
         .launch()
         .await?;
     Ok(())
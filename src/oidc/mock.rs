@@ -0,0 +1,154 @@
+use crate::oidc::{Flow, Identity, IdentityProvider};
+use crate::{Error, Result};
+
+use chrono::Utc;
+use openidconnect::CsrfToken;
+use rocket::http::Status;
+use serde::Deserialize;
+
+/// Seção `oidc` da configuração, com `provider = "mock"`
+#[derive(Clone, Debug, Deserialize)]
+pub struct MockConfig {
+    /// Para onde o login volta: a rota `/oidc/callback`
+    pub redirect_url: String,
+    /// Email da identidade confirmada em todo login
+    pub email: String,
+    #[serde(default = "default_verified")]
+    pub email_verified: bool,
+    #[serde(default = "default_name")]
+    pub name: String,
+}
+
+fn default_verified() -> bool {
+    true
+}
+
+fn default_name() -> String {
+    "Mock".into()
+}
+
+/// Emissor que não sai do servidor, para desenvolvimento e testes.
+///
+/// Todo login é aceito de imediato, com a identidade da configuração: a URL de autorização já é a
+/// de retorno, com o `state` e um código de uso único. O `state` e a validade do login são
+/// verificados como os de um provedor de verdade.
+#[derive(Debug)]
+pub struct MockIssuer {
+    config: MockConfig,
+}
+
+impl MockIssuer {
+    /// Emissor das identidades, como aparece nelas
+    pub const ISSUER: &'static str = "mock";
+
+    pub fn new(config: MockConfig) -> MockIssuer {
+        MockIssuer { config }
+    }
+}
+
+#[rocket::async_trait]
+impl IdentityProvider for MockIssuer {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+    async fn authorize(&self, redirect: Option<String>) -> Result<(String, Flow)> {
+        let flow = Flow {
+            state: CsrfToken::new_random().secret().clone(),
+            // O código do login é o nonce, que só o cookie conhece
+            nonce: CsrfToken::new_random().secret().clone(),
+            pkce_verifier: String::new(),
+            redirect,
+            expires: Utc::now() + Flow::lifetime(),
+        };
+        let url = format!(
+            "{}?state={}&code={}",
+            self.config.redirect_url, flow.state, flow.nonce
+        );
+        Ok((url, flow))
+    }
+    async fn authenticate(&self, flow: Flow, state: &str, code: &str) -> Result<Identity> {
+        flow.check(state)?;
+        if code != flow.nonce {
+            return Err(Error::builder()
+                .code(Status::Unauthorized)
+                .description("Código de autorização inválido")
+                .build());
+        }
+        Ok(Identity {
+            issuer: MockIssuer::ISSUER.into(),
+            subject: self.config.email.clone(),
+            email: Some(self.config.email.clone()),
+            email_verified: self.config.email_verified,
+            name: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issuer() -> MockIssuer {
+        MockIssuer::new(MockConfig {
+            redirect_url: "http://localhost/oidc/callback".into(),
+            email: "a@b.com".into(),
+            email_verified: true,
+            name: "Mock".into(),
+        })
+    }
+
+    /// Extrai um parâmetro da URL de autorização
+    fn param(url: &str, name: &str) -> String {
+        let query = url.split_once('?').unwrap().1;
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+            .into()
+    }
+
+    #[rocket::async_test]
+    async fn login_round_trip() {
+        let issuer = issuer();
+        let (url, flow) = issuer.authorize(Some("/".into())).await.unwrap();
+        assert!(url.starts_with("http://localhost/oidc/callback?"));
+        assert_eq!(flow.redirect.as_deref(), Some("/"));
+
+        let (state, code) = (param(&url, "state"), param(&url, "code"));
+        let identity = issuer.authenticate(flow, &state, &code).await.unwrap();
+        assert_eq!(identity.issuer, MockIssuer::ISSUER);
+        assert_eq!(identity.subject, "a@b.com");
+        assert_eq!(identity.email.as_deref(), Some("a@b.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[rocket::async_test]
+    async fn wrong_state_or_code() {
+        let issuer = issuer();
+        let (url, flow) = issuer.authorize(None).await.unwrap();
+        let (state, code) = (param(&url, "state"), param(&url, "code"));
+
+        let error = issuer
+            .authenticate(flow.clone(), "outro", &code)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Status::BadRequest);
+        let error = issuer
+            .authenticate(flow, &state, "outro")
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn expired_flow() {
+        let issuer = issuer();
+        let (url, mut flow) = issuer.authorize(None).await.unwrap();
+        flow.expires = Utc::now();
+        let error = issuer
+            .authenticate(flow, &param(&url, "state"), &param(&url, "code"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,247 @@
+//! Login por um provedor de identidade externo, via OpenID Connect (fluxo authorization code,
+//! com PKCE)
+pub mod mock;
+pub use mock::MockIssuer;
+
+use crate::{Error, Result};
+
+use chrono::{DateTime, Duration, Utc};
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+
+/// Seção `oidc` da configuração, com `provider = "oidc"` (o padrão). Caso ausente, o login por
+/// OpenID Connect fica desativado
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// URL do provedor, de onde é lida a configuração (`/.well-known/openid-configuration`)
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Para onde o provedor manda o usuário de volta: a rota `/oidc/callback`
+    pub redirect_url: String,
+    /// Nome do provedor, mostrado na página de login
+    #[serde(default = "default_name")]
+    pub name: String,
+}
+
+fn default_name() -> String {
+    "OpenID Connect".into()
+}
+
+/// Dados de um login em andamento, guardados (cifrados) num cookie até o provedor mandar o
+/// usuário de volta
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Flow {
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    /// Página para onde mandar o usuário depois do login, caso tenha começado pelo site
+    pub redirect: Option<String>,
+    pub expires: DateTime<Utc>,
+}
+
+impl Flow {
+    /// Quanto tempo o usuário tem para completar o login no provedor
+    pub fn lifetime() -> Duration {
+        Duration::minutes(10)
+    }
+    /// Retorna erro caso o `state` devolvido pelo provedor não seja o desse login, ou caso ele
+    /// tenha expirado
+    pub fn check(&self, state: &str) -> Result<()> {
+        if state != self.state || self.expires <= Utc::now() {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Login expirado ou iniciado em outro navegador, tente novamente")
+                .build());
+        }
+        Ok(())
+    }
+}
+
+/// Identidade confirmada pelo provedor
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// Erro de comunicação com o provedor
+fn provider_error<E: StdError + Send + Sync + 'static>(e: E) -> Error {
+    Error::builder()
+        .code(Status::BadGateway)
+        .source(Box::new(e))
+        .description("Não foi possível se comunicar com o provedor de identidade")
+        .build()
+}
+
+/// Um provedor de identidade externo
+#[rocket::async_trait]
+pub trait IdentityProvider: Send + Sync {
+    /// Nome do provedor, para ser mostrado aos usuários
+    fn name(&self) -> &str;
+    /// Começa um login: retorna a URL do provedor para onde mandar o usuário e os dados que
+    /// devem ser guardados até ele voltar
+    async fn authorize(&self, redirect: Option<String>) -> Result<(String, Flow)>;
+    /// Termina um login, com o `state` e o código devolvidos pelo provedor
+    async fn authenticate(&self, flow: Flow, state: &str, code: &str) -> Result<Identity>;
+}
+
+/// Provedor OpenID Connect de verdade, configurado pela seção `oidc`
+pub struct Provider {
+    config: Config,
+}
+
+impl Provider {
+    pub fn new(config: Config) -> Result<Provider> {
+        IssuerUrl::new(config.issuer.clone()).map_err(|e| {
+            Error::builder()
+                .source(Box::new(e))
+                .description("URL do provedor de identidade inválida")
+                .build()
+        })?;
+        RedirectUrl::new(config.redirect_url.clone()).map_err(|e| {
+            Error::builder()
+                .source(Box::new(e))
+                .description("URL de retorno do provedor de identidade inválida")
+                .build()
+        })?;
+        Ok(Provider { config })
+    }
+    /// Lê a configuração do provedor (endpoints e chaves). Ela é lida a cada login, para
+    /// acompanhar a troca das chaves
+    async fn client(&self) -> Result<CoreClient> {
+        let issuer = IssuerUrl::new(self.config.issuer.clone()).map_err(provider_error)?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client)
+            .await
+            .map_err(provider_error)?;
+        let redirect =
+            RedirectUrl::new(self.config.redirect_url.clone()).map_err(provider_error)?;
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(self.config.client_id.clone()),
+            self.config.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(redirect))
+    }
+}
+
+#[rocket::async_trait]
+impl IdentityProvider for Provider {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+    async fn authorize(&self, redirect: Option<String>) -> Result<(String, Flow)> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".into()))
+            .add_scope(Scope::new("profile".into()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        let flow = Flow {
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+            redirect,
+            expires: Utc::now() + Flow::lifetime(),
+        };
+        Ok((url.to_string(), flow))
+    }
+    /// Troca o código pelos tokens e valida o ID token (assinatura, emissor, audiência, validade
+    /// e nonce)
+    async fn authenticate(&self, flow: Flow, state: &str, code: &str) -> Result<Identity> {
+        flow.check(state)?;
+        let client = self.client().await?;
+        let response = client
+            .exchange_code(AuthorizationCode::new(code.into()))
+            .set_pkce_verifier(PkceCodeVerifier::new(flow.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(provider_error)?;
+        let id_token = response.id_token().ok_or_else(|| {
+            Error::builder()
+                .code(Status::BadGateway)
+                .description("O provedor de identidade não retornou um ID token")
+                .build()
+        })?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(flow.nonce))
+            .map_err(|e| {
+                Error::builder()
+                    .code(Status::Unauthorized)
+                    .source(Box::new(e))
+                    .description("O ID token do provedor de identidade é inválido")
+                    .build()
+            })?;
+        Ok(Identity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            email: claims.email().map(|email| email.to_string()),
+            email_verified: claims.email_verified().unwrap_or(false),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+        })
+    }
+}
+
+/// Lê a seção `oidc` da configuração
+fn section<T: DeserializeOwned>(figment: &Figment) -> Result<T> {
+    figment.extract_inner("oidc").map_err(|e| {
+        Error::builder()
+            .description(&format!("Configuração de OpenID Connect inválida: {}", e))
+            .build()
+    })
+}
+
+/// Provedor gerenciado pelo Rocket, caso o login por OpenID Connect esteja configurado
+pub type Providers = Option<Box<dyn IdentityProvider>>;
+
+/// Lê a seção `oidc` da configuração, caso exista, e gerencia o provedor escolhido pelo campo
+/// `provider`: `oidc` (o padrão) ou `mock`, para desenvolvimento e testes
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("OpenID Connect", |rocket| async {
+        let figment = rocket.figment();
+        if figment.find_value("oidc").is_err() {
+            return Ok(rocket.manage(Providers::None));
+        }
+        let kind: String = figment
+            .extract_inner("oidc.provider")
+            .unwrap_or_else(|_| "oidc".into());
+        let provider: Result<Box<dyn IdentityProvider>> = match kind.as_str() {
+            "oidc" => section(figment)
+                .and_then(Provider::new)
+                .map(|provider| Box::new(provider) as _),
+            "mock" => section(figment).map(|config| Box::new(MockIssuer::new(config)) as _),
+            other => Err(Error::builder()
+                .description(&format!("Provedor de identidade desconhecido: '{}'", other))
+                .build()),
+        };
+        match provider {
+            Ok(provider) => Ok(rocket.manage(Providers::Some(provider))),
+            Err(e) => {
+                rocket::error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
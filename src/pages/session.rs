@@ -0,0 +1,195 @@
+use crate::csrf::CsrfToken;
+use crate::mail::{Mailer, Mailers};
+use crate::oidc::Providers;
+use crate::schema::{
+    ClientInfo, EmailVerification, LoginAttempt, Session, User, UserToken, SESSION_COOKIE,
+};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use rocket::form::{Form, FromForm};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::response::{status, Redirect};
+use rocket::{get, post, State};
+use rocket_dyn_templates::Template;
+use serde_json::json;
+
+/// Reexibe um formulário com uma mensagem de erro
+fn form_error(
+    template: &'static str,
+    csrf: &CsrfToken,
+    email: &str,
+    error: Error,
+) -> status::Custom<Template> {
+    status::Custom(
+        error.code(),
+        Template::render(
+            template,
+            json!({ "csrf": csrf.as_str(), "email": email, "error": error.message() }),
+        ),
+    )
+}
+
+#[get("/login")]
+fn login_page(csrf: CsrfToken, oidc: &State<Providers>) -> Template {
+    Template::render(
+        "login",
+        json!({ "csrf": csrf.as_str(), "oidc": oidc.as_ref().map(|provider| provider.name()) }),
+    )
+}
+
+#[derive(Debug, FromForm)]
+struct LoginForm {
+    csrf: String,
+    email: String,
+    password: String,
+    code: Option<String>,
+}
+
+/// Verifica as credenciais e abre uma nova sessão
+async fn login(
+    db: &Database,
+    key: &TokenKey,
+    client: ClientInfo,
+    form: &LoginForm,
+) -> Result<Session> {
+    let user = LoginAttempt::authenticate(
+        db,
+        key,
+        &form.email,
+        &form.password,
+        form.code.as_deref(),
+        &client,
+    )
+    .await?;
+    Session::create(db, key, &user, client).await
+}
+
+/// Guarda o token da sessão recém aberta num cookie
+fn start_session(cookies: &CookieJar<'_>, session: Session) {
+    if let Some(token) = session.token {
+        cookies.add_private(UserToken::from(token).cookie());
+    }
+}
+
+#[post("/login", data = "<form>")]
+async fn login_submit(
+    db: Database,
+    key: &State<TokenKey>,
+    client: ClientInfo,
+    csrf: CsrfToken,
+    cookies: &CookieJar<'_>,
+    form: Form<LoginForm>,
+) -> std::result::Result<Redirect, status::Custom<Template>> {
+    let session = match csrf.verify(&form.csrf) {
+        Ok(()) => login(&db, key, client, &form).await,
+        Err(e) => Err(e),
+    };
+    match session {
+        Ok(session) => {
+            start_session(cookies, session);
+            Ok(Redirect::to("/"))
+        }
+        Err(e) => Err(form_error("login", &csrf, &form.email, e)),
+    }
+}
+
+#[get("/register")]
+fn register_page(csrf: CsrfToken) -> Template {
+    Template::render("register", json!({ "csrf": csrf.as_str() }))
+}
+
+#[derive(Debug, FromForm)]
+struct RegisterForm {
+    csrf: String,
+    name: String,
+    cpf: String,
+    email: String,
+    password: String,
+}
+
+/// Valida o formulário e registra um novo usuário, já abrindo uma sessão
+async fn register(
+    db: &Database,
+    key: &TokenKey,
+    mailer: &dyn Mailer,
+    client: ClientInfo,
+    form: &RegisterForm,
+) -> Result<Session> {
+    if form.name.trim().is_empty() || form.email.trim().is_empty() || form.password.is_empty() {
+        return Err(Error::builder()
+            .code(Status::BadRequest)
+            .description("Preencha todos os campos")
+            .build());
+    }
+    let user = User {
+        email: form.email.trim().into(),
+        password: User::hash_password(&form.password)?,
+        name: form.name.trim().into(),
+        token: None,
+        admin: false,
+        verified: false,
+        cpf: User::parse_cpf(&form.cpf)?,
+    };
+    user.create(db).await?;
+    EmailVerification::send(db, key, mailer, &user).await?;
+    Session::create(db, key, &user, client).await
+}
+
+#[post("/register", data = "<form>")]
+async fn register_submit(
+    db: Database,
+    key: &State<TokenKey>,
+    mailer: &State<Mailers>,
+    client: ClientInfo,
+    csrf: CsrfToken,
+    cookies: &CookieJar<'_>,
+    form: Form<RegisterForm>,
+) -> std::result::Result<Redirect, status::Custom<Template>> {
+    let session = match csrf.verify(&form.csrf) {
+        Ok(()) => register(&db, key, mailer.as_ref(), client, &form).await,
+        Err(e) => Err(e),
+    };
+    match session {
+        Ok(session) => {
+            start_session(cookies, session);
+            Ok(Redirect::to("/"))
+        }
+        Err(e) => Err(form_error("register", &csrf, &form.email, e)),
+    }
+}
+
+#[derive(Debug, FromForm)]
+struct LogoutForm {
+    csrf: String,
+}
+
+#[post("/logout", data = "<form>")]
+async fn logout(
+    db: Database,
+    key: &State<TokenKey>,
+    csrf: CsrfToken,
+    cookies: &CookieJar<'_>,
+    form: Form<LogoutForm>,
+) -> Result<Redirect> {
+    csrf.verify(&form.csrf)?;
+    // Independente de haver uma sessão válida, o usuário volta para o login
+    if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+        let token = UserToken::from(cookie.value().to_string());
+        if let Ok(session) = Session::read_from_token(&db, key, &token).await {
+            session.delete(&db).await.ok();
+        }
+        cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    }
+    Ok(Redirect::to("/login"))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        login_page,
+        login_submit,
+        register_page,
+        register_submit,
+        logout
+    ]
+}
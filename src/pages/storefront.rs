@@ -0,0 +1,236 @@
+use crate::csrf::CsrfToken;
+use crate::guards::AuthenticatedUser;
+use crate::payment::Payments;
+use crate::routes::query::PageQuery;
+use crate::schema::{
+    Cart, Category, Product, ProductFilter, Shop, ShopFilter, Tag, User, UserToken, Variant,
+    SESSION_COOKIE,
+};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use rocket::form::{Form, FromForm};
+use rocket::http::{CookieJar, Status};
+use rocket::response::{status, Redirect};
+use rocket::{get, post, State};
+use rocket_dyn_templates::Template;
+use serde_json::{json, Value};
+
+#[get("/shops?<page..>", format = "html", rank = 2)]
+async fn shops(db: Database, page: PageQuery) -> Result<Template> {
+    let mut shops = Shop::list(&db, ShopFilter::default(), page.sort()?, page.page()?).await?;
+    let items = std::mem::take(&mut shops.items);
+    Ok(Template::render(
+        "shops",
+        json!({ "shops": items, "page": shops }),
+    ))
+}
+
+#[get("/shops/<slug>?<category>&<tag>&<page..>", format = "html", rank = 2)]
+async fn shop(
+    db: Database,
+    slug: String,
+    category: Option<String>,
+    tag: Option<String>,
+    page: PageQuery,
+) -> Result<Template> {
+    let shop = Shop::read(&db, &slug).await?;
+    let categories = Category::list_from_shop(&db, &shop).await?;
+    let category = match category {
+        Some(category) => Some(Category::read(&db, &category).await?),
+        None => None,
+    };
+    let tag = match tag {
+        Some(tag) => Some(Tag::read(&db, &shop, &tag).await?),
+        None => None,
+    };
+    let filter = ProductFilter {
+        shop: Some(shop.slug.clone()),
+        category: category.as_ref().map(|category| category.slug.clone()),
+        tag: tag.as_ref().map(|tag| tag.slug.clone()),
+        ..Default::default()
+    };
+    let mut products = Product::list(&db, filter, page.sort()?, page.page()?).await?;
+    let items = std::mem::take(&mut products.items);
+    Ok(Template::render(
+        "shop",
+        json!({
+            "shop": shop,
+            "products": items,
+            "page": products,
+            "categories": categories,
+            "category": category,
+            "tag": tag,
+        }),
+    ))
+}
+
+#[get("/categories", format = "html", rank = 2)]
+async fn categories(db: Database) -> Result<Template> {
+    let roots: Vec<Category> = Category::list(&db)
+        .await?
+        .into_iter()
+        .filter(|category| category.parent.is_none())
+        .collect();
+    Ok(Template::render(
+        "categories",
+        json!({ "categories": roots }),
+    ))
+}
+
+/// Produtos de todas as lojas na categoria ou nas subcategorias dela
+#[get("/categories/<slug>?<page..>", format = "html", rank = 2)]
+async fn category(db: Database, slug: String, page: PageQuery) -> Result<Template> {
+    let category = Category::read(&db, &slug).await?;
+    let ancestors = category.ancestors(&db).await?;
+    let children: Vec<Category> = Category::list(&db)
+        .await?
+        .into_iter()
+        .filter(|child| child.parent.as_deref() == Some(category.slug.as_str()))
+        .collect();
+    let filter = ProductFilter {
+        category: Some(category.slug.clone()),
+        ..Default::default()
+    };
+    let mut products = Product::list(&db, filter, page.sort()?, page.page()?).await?;
+    let items = std::mem::take(&mut products.items);
+    Ok(Template::render(
+        "category",
+        json!({
+            "category": category,
+            "ancestors": ancestors,
+            "children": children,
+            "products": items,
+            "page": products,
+        }),
+    ))
+}
+
+#[get("/shops/<slug>/products/<product>")]
+async fn product(db: Database, slug: String, product: String) -> Result<Template> {
+    let shop = Shop::read(&db, &slug).await?;
+    let product = Product::read(&db, &product).await?;
+    // O produto deve ser da loja na URL
+    if product.shop != shop.slug {
+        return Err(Error::builder()
+            .code(Status::NotFound)
+            .description("Produto não encontrado")
+            .build());
+    }
+    let categories = Category::list_from_product(&db, &product).await?;
+    let tags = Tag::list_from_product(&db, &product).await?;
+    let variants = Variant::list_from_product(&db, &product).await?;
+    Ok(Template::render(
+        "product",
+        json!({
+            "shop": shop,
+            "product": product,
+            "categories": categories,
+            "tags": tags,
+            "variants": variants,
+        }),
+    ))
+}
+
+/// Contexto da página do carrinho: os itens junto com os produtos e variantes
+async fn cart_context(db: &Database, shop: &Shop, cart: &Cart, csrf: &CsrfToken) -> Result<Value> {
+    let mut items = Vec::new();
+    for item in &cart.items {
+        let product = Product::read(db, &item.product).await?;
+        let variant = match item.variant {
+            Some(id) => Some(Variant::read(db, &product, id).await?),
+            None => None,
+        };
+        items.push(json!({
+            "item": item,
+            "product": product,
+            "variant": variant,
+            "subtotal": item.price * rust_decimal::Decimal::from(item.amount),
+        }));
+    }
+    Ok(json!({
+        "shop": shop,
+        "items": items,
+        "total": cart.total(),
+        "csrf": csrf.as_str(),
+    }))
+}
+
+/// Carrinho do usuário nessa loja. Sem uma sessão, leva ao login
+#[get("/shops/<slug>/cart", format = "html", rank = 2)]
+async fn cart(
+    db: Database,
+    slug: String,
+    csrf: CsrfToken,
+    requester: Result<AuthenticatedUser>,
+) -> Result<std::result::Result<Template, Redirect>> {
+    let requester = match requester {
+        Ok(requester) => requester,
+        Err(_) => return Ok(Err(Redirect::to("/login"))),
+    };
+    let shop = Shop::read(&db, &slug).await?;
+    let cart = Cart::read(&db, &requester, Some(&shop)).await?;
+    let context = cart_context(&db, &shop, &cart, &csrf).await?;
+    Ok(Ok(Template::render("cart", context)))
+}
+
+#[derive(Debug, FromForm)]
+struct CheckoutForm {
+    csrf: String,
+}
+
+/// Usuário da sessão do cookie. Formulários não mandam o header anti-CSRF que o guard de
+/// autenticação exige, então o token deve ter sido verificado pelo campo `csrf`
+async fn cookie_user(db: &Database, key: &TokenKey, cookies: &CookieJar<'_>) -> Result<User> {
+    let cookie = cookies.get_private(SESSION_COOKIE).ok_or_else(|| {
+        Error::builder()
+            .code(Status::Unauthorized)
+            .description("Faça login para continuar")
+            .build()
+    })?;
+    User::read_from_token(db, key, &UserToken::from(cookie.value().to_string())).await
+}
+
+/// Compra os itens do carrinho da loja. O carrinho só é esvaziado depois do pagamento
+#[post("/shops/<slug>/cart/checkout", data = "<form>")]
+async fn checkout(
+    db: Database,
+    slug: String,
+    key: &State<TokenKey>,
+    payments: &State<Payments>,
+    csrf: CsrfToken,
+    cookies: &CookieJar<'_>,
+    form: Form<CheckoutForm>,
+) -> Result<status::Custom<Template>> {
+    let shop = Shop::read(&db, &slug).await?;
+    let result = async {
+        csrf.verify(&form.csrf)?;
+        let user = cookie_user(&db, key, cookies).await?;
+        user.check_verified()?;
+        let cart = Cart::read(&db, &user, Some(&shop)).await?;
+        let mut order = cart.checkout(&db, &user).await?;
+        order.pay(&db, payments.as_ref(), &user).await?;
+        cart.clear(&db).await?;
+        Ok::<_, Error>((user, order))
+    }
+    .await;
+
+    // A página mostra o carrinho (vazio, caso a compra tenha dado certo) e o resultado
+    let (status, user, message) = match result {
+        Ok((user, order)) => (Status::Ok, user, json!({ "order": order })),
+        Err(e) => {
+            let user = cookie_user(&db, key, cookies).await?;
+            (e.code(), user, json!({ "error": e.message() }))
+        }
+    };
+    let cart = Cart::read(&db, &user, Some(&shop)).await?;
+    let mut context = cart_context(&db, &shop, &cart, &csrf).await?;
+    if let (Some(context), Some(message)) = (context.as_object_mut(), message.as_object()) {
+        context.extend(message.clone());
+    }
+    Ok(status::Custom(status, Template::render("cart", context)))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![shops, shop, product, categories, category, cart, checkout]
+}
@@ -0,0 +1,195 @@
+use crate::payment::{declined, PaymentProvider, PaymentStatus};
+use crate::{Error, Result};
+
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+struct MockPayment {
+    amount: Decimal,
+    refunded: Decimal,
+    status: PaymentStatus,
+}
+
+/// Provedor de pagamentos em memória, para desenvolvimento e testes.
+///
+/// Aprova qualquer cobrança positiva, exceto as acima de `decline_above` (caso definido).
+#[derive(Debug, Default)]
+pub struct MockProvider {
+    decline_above: Option<Decimal>,
+    payments: Mutex<HashMap<String, MockPayment>>,
+}
+
+impl MockProvider {
+    pub fn new(decline_above: Option<Decimal>) -> MockProvider {
+        MockProvider {
+            decline_above,
+            payments: Mutex::new(HashMap::new()),
+        }
+    }
+    fn with_payment<T>(
+        &self,
+        reference: &str,
+        f: impl FnOnce(&mut MockPayment) -> Result<T>,
+    ) -> Result<T> {
+        let mut payments = self.payments.lock().unwrap_or_else(|e| e.into_inner());
+        let payment = payments.get_mut(reference).ok_or_else(|| {
+            Error::builder()
+                .code(Status::NotFound)
+                .description("Pagamento não encontrado")
+                .build()
+        })?;
+        f(payment)
+    }
+}
+
+fn invalid_state(status: PaymentStatus) -> Error {
+    Error::builder()
+        .code(Status::Conflict)
+        .description(&format!(
+            "Operação inválida para um pagamento '{}'",
+            status.as_str()
+        ))
+        .build()
+}
+
+#[rocket::async_trait]
+impl PaymentProvider for MockProvider {
+    async fn authorize(&self, _payer: &str, amount: Decimal) -> Result<String> {
+        if amount <= Decimal::ZERO {
+            return Err(declined("o valor deve ser positivo"));
+        }
+        if matches!(self.decline_above, Some(limit) if amount > limit) {
+            return Err(declined("limite excedido"));
+        }
+        let reference = format!("mock_{}", Uuid::new_v4().to_simple());
+        let payment = MockPayment {
+            amount,
+            refunded: Decimal::ZERO,
+            status: PaymentStatus::Authorized,
+        };
+        self.payments
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(reference.clone(), payment);
+        Ok(reference)
+    }
+    async fn capture(&self, reference: &str) -> Result<()> {
+        self.with_payment(reference, |payment| match payment.status {
+            PaymentStatus::Authorized => {
+                payment.status = PaymentStatus::Captured;
+                Ok(())
+            }
+            status => Err(invalid_state(status)),
+        })
+    }
+    async fn refund(&self, reference: &str, amount: Decimal) -> Result<()> {
+        self.with_payment(reference, |payment| {
+            if amount <= Decimal::ZERO || payment.refunded + amount > payment.amount {
+                return Err(Error::builder()
+                    .code(Status::BadRequest)
+                    .description("Valor de reembolso inválido")
+                    .build());
+            }
+            match payment.status {
+                PaymentStatus::Authorized | PaymentStatus::Captured => {
+                    payment.refunded += amount;
+                    if payment.status == PaymentStatus::Authorized
+                        || payment.refunded == payment.amount
+                    {
+                        payment.status = PaymentStatus::Refunded;
+                    }
+                    Ok(())
+                }
+                status => Err(invalid_state(status)),
+            }
+        })
+    }
+    async fn status(&self, reference: &str) -> Result<PaymentStatus> {
+        self.with_payment(reference, |payment| Ok(payment.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payment::charge;
+
+    #[rocket::async_test]
+    async fn charge_captures_the_payment() {
+        let provider = MockProvider::new(None);
+        let reference = charge(&provider, "a@b.com", Decimal::from(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            provider.status(&reference).await.unwrap(),
+            PaymentStatus::Captured
+        );
+    }
+
+    #[rocket::async_test]
+    async fn charge_declines_invalid_amounts() {
+        let provider = MockProvider::new(Some(Decimal::from(100)));
+        for amount in &[Decimal::ZERO, Decimal::from(-1), Decimal::from(101)] {
+            let error = charge(&provider, "a@b.com", *amount).await.unwrap_err();
+            assert_eq!(error.code(), Status::PaymentRequired);
+        }
+        assert!(charge(&provider, "a@b.com", Decimal::from(100))
+            .await
+            .is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn partial_refunds_until_the_total() {
+        let provider = MockProvider::new(None);
+        let reference = charge(&provider, "a@b.com", Decimal::from(10))
+            .await
+            .unwrap();
+        provider.refund(&reference, Decimal::from(4)).await.unwrap();
+        assert_eq!(
+            provider.status(&reference).await.unwrap(),
+            PaymentStatus::Captured
+        );
+        let error = provider
+            .refund(&reference, Decimal::from(7))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Status::BadRequest);
+        provider.refund(&reference, Decimal::from(6)).await.unwrap();
+        assert_eq!(
+            provider.status(&reference).await.unwrap(),
+            PaymentStatus::Refunded
+        );
+        let error = provider
+            .refund(&reference, Decimal::from(1))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn refund_releases_an_authorization() {
+        let provider = MockProvider::new(None);
+        let reference = provider
+            .authorize("a@b.com", Decimal::from(10))
+            .await
+            .unwrap();
+        provider.refund(&reference, Decimal::from(1)).await.unwrap();
+        assert_eq!(
+            provider.status(&reference).await.unwrap(),
+            PaymentStatus::Refunded
+        );
+        let error = provider.capture(&reference).await.unwrap_err();
+        assert_eq!(error.code(), Status::Conflict);
+    }
+
+    #[rocket::async_test]
+    async fn unknown_payments() {
+        let provider = MockProvider::new(None);
+        let error = provider.status("mock_unknown").await.unwrap_err();
+        assert_eq!(error.code(), Status::NotFound);
+    }
+}
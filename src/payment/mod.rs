@@ -0,0 +1,119 @@
+pub mod mock;
+pub use mock::MockProvider;
+
+use crate::{Error, Result};
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Situação de um pagamento
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStatus {
+    /// Ainda não cobrado
+    Pending,
+    /// Valor reservado, mas ainda não capturado
+    Authorized,
+    /// Valor cobrado
+    Captured,
+    /// Valor devolvido (ou reserva liberada)
+    Refunded,
+    /// Cobrança recusada ou que não pôde ser completada
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Authorized => "authorized",
+            PaymentStatus::Captured => "captured",
+            PaymentStatus::Refunded => "refunded",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+impl TryFrom<&str> for PaymentStatus {
+    type Error = Error;
+    fn try_from(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(PaymentStatus::Pending),
+            "authorized" => Ok(PaymentStatus::Authorized),
+            "captured" => Ok(PaymentStatus::Captured),
+            "refunded" => Ok(PaymentStatus::Refunded),
+            "failed" => Ok(PaymentStatus::Failed),
+            _ => Err(Error::builder()
+                .description(&format!("Status de pagamento desconhecido: '{}'", status))
+                .build()),
+        }
+    }
+}
+
+/// Um meio de cobrança (gateway de pagamento)
+#[rocket::async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Reserva um valor do pagador, retornando a referência do pagamento no provedor
+    async fn authorize(&self, payer: &str, amount: Decimal) -> Result<String>;
+    /// Efetiva a cobrança de um valor reservado
+    async fn capture(&self, reference: &str) -> Result<()>;
+    /// Devolve parte (ou todo) de um valor cobrado, ou libera uma reserva
+    async fn refund(&self, reference: &str, amount: Decimal) -> Result<()>;
+    /// Consulta a situação de um pagamento
+    async fn status(&self, reference: &str) -> Result<PaymentStatus>;
+}
+
+/// Provedor de pagamentos em uso, gerenciado pelo rocket
+pub type Payments = Box<dyn PaymentProvider>;
+
+/// Autoriza e captura um valor. Caso a captura falhe, a reserva é liberada
+pub async fn charge(
+    provider: &dyn PaymentProvider,
+    payer: &str,
+    amount: Decimal,
+) -> Result<String> {
+    let reference = provider.authorize(payer, amount).await?;
+    if let Err(e) = provider.capture(&reference).await {
+        provider.refund(&reference, amount).await.ok();
+        return Err(e);
+    }
+    Ok(reference)
+}
+
+/// Erro para pagamentos recusados
+pub fn declined(reason: &str) -> Error {
+    Error::builder()
+        .code(Status::PaymentRequired)
+        .description(&format!("Pagamento recusado: {}", reason))
+        .build()
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    provider: String,
+    decline_above: Option<Decimal>,
+}
+
+/// Lê a seção `payment` da configuração e gerencia o provedor escolhido
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Payment Provider", |rocket| async {
+        let config: Config = match rocket.figment().extract_inner("payment") {
+            Ok(config) => config,
+            Err(e) => {
+                rocket::error!("Configuração de pagamento inválida: {}", e);
+                return Err(rocket);
+            }
+        };
+        let provider: Payments = match config.provider.as_str() {
+            "mock" => Box::new(MockProvider::new(config.decline_above)),
+            other => {
+                rocket::error!("Provedor de pagamento desconhecido: '{}'", other);
+                return Err(rocket);
+            }
+        };
+        Ok(rocket.manage(provider))
+    })
+}
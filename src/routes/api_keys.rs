@@ -0,0 +1,59 @@
+use crate::guards::{ManageApiKeys, ShopManager};
+use crate::schema::{ApiKey, ApiScope};
+use crate::token::TokenKey;
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::{delete, get, post, State};
+use serde::Deserialize;
+
+#[get("/<_>/api-keys")]
+async fn list(
+    db: Database,
+    owner: Result<ShopManager<ManageApiKeys>>,
+) -> Result<Json<Vec<ApiKey>>> {
+    let shop = owner?.shop;
+    let keys = ApiKey::list_from_shop(&db, &shop).await?;
+    Ok(Json(keys))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    name: String,
+    scopes: Vec<ApiScope>,
+}
+
+/// Cria uma chave. Essa é a única vez que ela é mostrada
+#[post("/<_>/api-keys", data = "<body>")]
+async fn create(
+    db: Database,
+    key: &State<TokenKey>,
+    owner: Result<ShopManager<ManageApiKeys>>,
+    body: BodyResult<'_, CreateRequest>,
+) -> Result<status::Created<Json<ApiKey>>> {
+    let body = body?.into_inner();
+    let ShopManager { user, shop, .. } = owner?;
+    let api_key = ApiKey::create(&db, key, &shop, &user, &body.name, body.scopes).await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/shops/{}/api-keys/{}",
+        api_key.shop, api_key.id
+    ))
+    .body(Json(api_key)))
+}
+
+#[delete("/<_>/api-keys/<id>")]
+async fn revoke(
+    db: Database,
+    id: Uuid,
+    owner: Result<ShopManager<ManageApiKeys>>,
+) -> Result<status::NoContent> {
+    let ShopManager { user, shop, .. } = owner?;
+    let api_key = ApiKey::read(&db, &shop, id).await?;
+    api_key.delete(&db, &user).await?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list, create, revoke]
+}
@@ -0,0 +1,52 @@
+use crate::guards::AdminUser;
+use crate::routes::query::{parse_time, PageQuery};
+use crate::schema::{AuditEntry, AuditFilter, Page};
+use crate::{Database, Error, Result};
+use rocket::get;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use std::convert::TryInto;
+
+/// Consulta o registro de ações, paginado, das mais recentes para as mais antigas. `since` e
+/// `until` (exclusivo) são horários em RFC 3339
+#[get("/?<actor>&<api_key>&<action>&<target_type>&<target>&<since>&<until>&<page..>")]
+#[allow(clippy::too_many_arguments)]
+async fn list(
+    db: Database,
+    admin: Result<AdminUser>,
+    actor: Option<String>,
+    api_key: Option<Uuid>,
+    action: Option<String>,
+    target_type: Option<String>,
+    target: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    page: PageQuery,
+) -> Result<Json<Page<AuditEntry>>> {
+    admin?;
+    let action = match action {
+        Some(action) => Some(
+            action
+                .as_str()
+                .try_into()
+                .map_err(|e: Error| e.edit().code(Status::BadRequest).build())?,
+        ),
+        None => None,
+    };
+    let filter = AuditFilter {
+        actor,
+        api_key,
+        action,
+        target_type,
+        target,
+        since: parse_time(since)?,
+        until: parse_time(until)?,
+    };
+    let entries = AuditEntry::list(&db, filter, page.page()?).await?;
+    Ok(Json(entries))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list]
+}
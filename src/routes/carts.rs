@@ -0,0 +1,123 @@
+use crate::guards::AuthenticatedUser;
+use crate::payment::Payments;
+use crate::schema::{Cart, Order, Product, Shop, User, Variant};
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::{delete, get, patch, post, State};
+use serde::Deserialize;
+
+/// Lê o carrinho do usuário, na loja (caso especificada) ou o geral
+async fn read_cart(db: &Database, requester: &User, shop: Option<String>) -> Result<Cart> {
+    let shop = match shop {
+        Some(shop) => Some(Shop::read(db, &shop).await?),
+        None => None,
+    };
+    Cart::read(db, requester, shop.as_ref()).await
+}
+
+#[get("/?<shop>")]
+async fn read(
+    db: Database,
+    shop: Option<String>,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Cart>> {
+    let cart = read_cart(&db, &requester?.0, shop).await?;
+    Ok(Json(cart))
+}
+
+#[derive(Debug, Deserialize)]
+struct AddRequest {
+    product: String,
+    /// Obrigatória para produtos com variantes
+    variant: Option<Uuid>,
+    amount: i32,
+}
+
+#[post("/items?<shop>", data = "<body>")]
+async fn add(
+    db: Database,
+    shop: Option<String>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, AddRequest>,
+) -> Result<Json<Cart>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let cart = read_cart(&db, &requester, shop.clone()).await?;
+    let product = Product::read(&db, &body.product).await?;
+
+    let variant = match body.variant {
+        Some(id) => Some(Variant::read(&db, &product, id).await?),
+        None => None,
+    };
+
+    cart.add(&db, &product, variant.as_ref(), body.amount)
+        .await?;
+    let cart = read_cart(&db, &requester, shop).await?;
+    Ok(Json(cart))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateRequest {
+    amount: i32,
+}
+
+/// Altera a quantidade de um item. Itens de variantes são identificados também por `variant`
+#[patch("/items/<product>?<variant>&<shop>", data = "<body>")]
+async fn update(
+    db: Database,
+    product: String,
+    variant: Option<Uuid>,
+    shop: Option<String>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, UpdateRequest>,
+) -> Result<Json<Cart>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let cart = read_cart(&db, &requester, shop.clone()).await?;
+
+    cart.set_amount(&db, &product, variant, body.amount).await?;
+    let cart = read_cart(&db, &requester, shop).await?;
+    Ok(Json(cart))
+}
+
+#[delete("/items/<product>?<variant>&<shop>")]
+async fn remove(
+    db: Database,
+    product: String,
+    variant: Option<Uuid>,
+    shop: Option<String>,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Cart>> {
+    let requester = requester?;
+    let cart = read_cart(&db, &requester, shop.clone()).await?;
+
+    cart.remove(&db, &product, variant).await?;
+    let cart = read_cart(&db, &requester, shop).await?;
+    Ok(Json(cart))
+}
+
+#[post("/checkout?<shop>")]
+async fn checkout(
+    db: Database,
+    payments: &State<Payments>,
+    shop: Option<String>,
+    requester: Result<AuthenticatedUser>,
+) -> Result<status::Created<Json<Order>>> {
+    let requester = requester?;
+    requester.check_verified()?;
+    let cart = read_cart(&db, &requester, shop).await?;
+
+    let mut order = cart.checkout(&db, &requester).await?;
+    order.pay(&db, payments.as_ref(), &requester).await?;
+    cart.clear(&db).await?;
+    Ok(
+        status::Created::new(format!("https://cincobola.misterio.me/orders/{}", order.id))
+            .body(Json(order)),
+    )
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![read, add, update, remove, checkout]
+}
@@ -0,0 +1,108 @@
+use crate::guards::AdminUser;
+use crate::schema::Category;
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, patch, post};
+use serde::{Deserialize, Deserializer};
+
+/// Todas as categorias. Cada uma diz qual é a sua categoria pai, para montar a árvore
+#[get("/", format = "json")]
+async fn list(db: Database) -> Result<Json<Vec<Category>>> {
+    let categories = Category::list(&db).await?;
+    Ok(Json(categories))
+}
+
+#[get("/<slug>", format = "json")]
+async fn read(db: Database, slug: String) -> Result<Json<Category>> {
+    let category = Category::read(&db, &slug).await?;
+    Ok(Json(category))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    slug: String,
+    name: String,
+    parent: Option<String>,
+}
+
+#[post("/", data = "<body>")]
+async fn create(
+    db: Database,
+    admin: Result<AdminUser>,
+    body: BodyResult<'_, CreateRequest>,
+) -> Result<status::Created<Json<Category>>> {
+    let body = body?.into_inner();
+    let admin = admin?;
+    if let Some(parent) = &body.parent {
+        Category::read(&db, parent).await?;
+    }
+    let category = Category {
+        slug: body.slug,
+        name: body.name,
+        parent: body.parent,
+    };
+    category.create(&db, &admin).await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/categories/{}",
+        category.slug
+    ))
+    .body(Json(category)))
+}
+
+/// Diferencia um campo omitido (`None`) de um `null` (`Some(None)`)
+fn nullable<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Option<String>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateRequest {
+    slug: Option<String>,
+    name: Option<String>,
+    /// `null` torna a categoria uma raiz
+    #[serde(default, deserialize_with = "nullable")]
+    parent: Option<Option<String>>,
+}
+
+#[patch("/<slug>", data = "<body>")]
+async fn update(
+    db: Database,
+    slug: String,
+    admin: Result<AdminUser>,
+    body: BodyResult<'_, UpdateRequest>,
+) -> Result<Json<Category>> {
+    let body = body?.into_inner();
+    let admin = admin?;
+    let mut category = Category::read(&db, &slug).await?;
+
+    let before = category.clone();
+    if let Some(x) = body.slug {
+        category.slug = x;
+    }
+    if let Some(x) = body.name {
+        category.name = x;
+    }
+    if let Some(x) = body.parent {
+        if let Some(parent) = &x {
+            Category::read(&db, parent).await?;
+        }
+        category.parent = x;
+    }
+
+    category.update(&db, &before, &admin).await?;
+    Ok(Json(category))
+}
+
+#[delete("/<slug>")]
+async fn delete(db: Database, slug: String, admin: Result<AdminUser>) -> Result<status::NoContent> {
+    let admin = admin?;
+    let category = Category::read(&db, &slug).await?;
+    category.delete(&db, &admin).await?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list, read, create, update, delete]
+}
@@ -0,0 +1,147 @@
+use crate::guards::{AuthenticatedUser, ManageMembers, ShopManager};
+use crate::mail::{Mail, Mailers};
+use crate::routes::query::PageQuery;
+use crate::schema::{Member, Page, Permission, Role, Shop, User};
+use crate::{BodyResult, Database, Error, Result};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, patch, post, State};
+use serde::Deserialize;
+
+/// Lista a equipe da loja, paginada. O gerente vem primeiro, como dono
+#[get("/<slug>/members?<page..>")]
+async fn list(
+    db: Database,
+    slug: String,
+    page: PageQuery,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Page<Member>>> {
+    let requester = requester?;
+    let shop = Shop::read(&db, &slug).await?;
+    shop.check_permission(&db, &requester, Permission::ViewMembers)
+        .await?;
+
+    let members = Member::list_from_shop(&db, &shop, page.page()?).await?;
+    Ok(Json(members))
+}
+
+/// Retorna erro caso o papel não possa ser dado pela equipe. Só o gerente da loja é dono, e
+/// trocá-lo é uma alteração da própria loja
+fn check_grantable(role: Role) -> Result<()> {
+    if role == Role::Owner {
+        return Err(Error::builder()
+            .code(Status::BadRequest)
+            .description(
+                "O papel de dono é apenas do gerente da loja, escolha 'editor' ou 'viewer'",
+            )
+            .build());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct AddRequest {
+    email: String,
+    role: Role,
+}
+
+/// Adiciona um usuário à equipe, avisando-o por email
+#[post("/<_>/members", data = "<body>")]
+async fn add(
+    db: Database,
+    mailer: &State<Mailers>,
+    owner: Result<ShopManager<ManageMembers>>,
+    body: BodyResult<'_, AddRequest>,
+) -> Result<status::Created<Json<Member>>> {
+    let body = body?.into_inner();
+    let ShopManager {
+        user: requester,
+        shop,
+        ..
+    } = owner?;
+    check_grantable(body.role)?;
+    let target = User::read(&db, &body.email).await?;
+
+    if target.email == shop.manager {
+        return Err(Error::builder()
+            .code(Status::BadRequest)
+            .description("O gerente da loja já faz parte da equipe")
+            .build());
+    }
+    let member = Member {
+        shop: shop.slug.clone(),
+        member: target.email.clone(),
+        role: body.role,
+    };
+    member.create(&db, &requester).await?;
+    mailer
+        .send(Mail {
+            to: target.email,
+            subject: format!("Você agora faz parte da equipe de {}", shop.name),
+            body: format!(
+                "Olá, {}!\n\n{} adicionou você à equipe da loja {}, com o papel '{}'.",
+                target.name,
+                requester.name,
+                shop.name,
+                member.role.as_str()
+            ),
+        })
+        .await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/shops/{}/members/{}",
+        member.shop, member.member
+    ))
+    .body(Json(member)))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateRequest {
+    role: Role,
+}
+
+#[patch("/<_>/members/<email>", data = "<body>")]
+async fn update(
+    db: Database,
+    email: String,
+    owner: Result<ShopManager<ManageMembers>>,
+    body: BodyResult<'_, UpdateRequest>,
+) -> Result<Json<Member>> {
+    let body = body?.into_inner();
+    let ShopManager {
+        user: requester,
+        shop,
+        ..
+    } = owner?;
+    check_grantable(body.role)?;
+
+    let mut member = Member::read(&db, &shop, &email).await?;
+    let before = member.clone();
+    member.role = body.role;
+    member.update(&db, &before, &requester).await?;
+    Ok(Json(member))
+}
+
+/// Remove um membro da equipe. Qualquer membro pode sair por conta própria
+#[delete("/<slug>/members/<email>")]
+async fn remove(
+    db: Database,
+    slug: String,
+    email: String,
+    requester: Result<AuthenticatedUser>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    let shop = Shop::read(&db, &slug).await?;
+
+    let member = Member::read(&db, &shop, &email).await?;
+    if member.member != requester.email {
+        shop.check_permission(&db, &requester, Permission::ManageMembers)
+            .await?;
+    }
+    member.delete(&db, &requester).await?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list, add, update, remove]
+}
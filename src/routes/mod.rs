@@ -1,5 +1,17 @@
+pub mod api_keys;
+pub mod audit;
+pub mod carts;
+pub mod categories;
+pub mod members;
+pub mod oidc;
+pub mod orders;
 pub mod products;
 pub mod purchases;
+pub mod query;
 pub mod session;
+pub mod settings;
 pub mod shops;
+pub mod tags;
+pub mod upload;
 pub mod users;
+pub mod variants;
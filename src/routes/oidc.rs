@@ -0,0 +1,198 @@
+use crate::oidc::{Flow, IdentityProvider, Providers};
+use crate::schema::{ClientInfo, Session, TwoFactor, User, UserIdentity, UserToken};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+use rocket::form::FromForm;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::{Redirect, Responder};
+use rocket::serde::json::Json;
+use rocket::{get, State};
+
+/// Nome do cookie privado que guarda o login em andamento
+const FLOW_COOKIE: &str = "oidc_flow";
+
+/// Provedor configurado, ou erro caso o login por OpenID Connect esteja desativado
+fn configured(provider: &Providers) -> Result<&dyn IdentityProvider> {
+    provider.as_deref().ok_or_else(|| {
+        Error::builder()
+            .code(Status::NotFound)
+            .description("Login por OpenID Connect não está configurado")
+            .build()
+    })
+}
+
+/// Se o redirecionamento é um caminho deste site. Navegadores tratam `//` e `/\` como o começo
+/// de outro endereço, e ignoram caracteres de controle, então eles são recusados
+fn local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control)
+}
+
+/// Manda o usuário para o provedor de identidade. Caso `redirect` seja dado (um caminho do
+/// site, como `/`), o login termina com o cookie de sessão e um redirecionamento para ele; caso
+/// contrário, com o usuário e o token em JSON, como em `POST /session`
+#[get("/login?<redirect>")]
+async fn login(
+    provider: &State<Providers>,
+    cookies: &CookieJar<'_>,
+    redirect: Option<String>,
+) -> Result<Redirect> {
+    let provider = configured(provider)?;
+    // Apenas caminhos locais, para que o login não sirva para mandar usuários a outros sites
+    let redirect = redirect.filter(|path| local_path(path));
+    let (url, flow) = provider.authorize(redirect).await?;
+    cookies.add_private(
+        Cookie::build(FLOW_COOKIE, serde_json::to_string(&flow)?)
+            .secure(true)
+            .http_only(true)
+            // O provedor manda o usuário de volta num redirecionamento vindo de outro site
+            .same_site(SameSite::Lax)
+            .finish(),
+    );
+    Ok(Redirect::to(url))
+}
+
+#[derive(Responder)]
+enum LoginResponse {
+    Redirect(Redirect),
+    Json(Json<User>),
+}
+
+/// Resposta do provedor: o código de autorização ou um erro
+#[derive(Debug, FromForm)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Para onde o provedor manda o usuário de volta
+#[get("/callback?<query..>")]
+async fn callback(
+    db: Database,
+    provider: &State<Providers>,
+    key: &State<TokenKey>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    query: CallbackQuery,
+) -> Result<LoginResponse> {
+    let provider = configured(provider)?;
+    if let Some(error) = query.error {
+        return Err(Error::builder()
+            .code(Status::Unauthorized)
+            .description(&format!(
+                "O provedor de identidade recusou o login: {}",
+                query.error_description.unwrap_or(error)
+            ))
+            .build());
+    }
+    let flow: Flow = match cookies.get_private(FLOW_COOKIE) {
+        Some(cookie) => {
+            cookies.remove_private(Cookie::named(FLOW_COOKIE));
+            serde_json::from_str(cookie.value())?
+        }
+        None => {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Login expirado ou iniciado em outro navegador, tente novamente")
+                .build())
+        }
+    };
+    let redirect = flow.redirect.clone();
+    let identity = provider
+        .authenticate(
+            flow,
+            query.state.as_deref().unwrap_or_default(),
+            query.code.as_deref().unwrap_or_default(),
+        )
+        .await?;
+
+    let mut user = UserIdentity::sign_in(&db, &identity).await?;
+    // O provedor não sabe do nosso segundo fator, e pular ele enfraqueceria a conta
+    if TwoFactor::is_enabled(&db, &user).await? {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Essa conta usa autenticação em dois fatores, entre com email e senha")
+            .build());
+    }
+    let session = Session::create(&db, key, &user, client).await?;
+    match redirect {
+        Some(path) => {
+            if let Some(token) = session.token {
+                cookies.add_private(UserToken::from(token).cookie());
+            }
+            Ok(LoginResponse::Redirect(Redirect::to(path)))
+        }
+        None => {
+            user.token = session.token;
+            Ok(LoginResponse::Json(Json(user)))
+        }
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![login, callback]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::mock::{MockConfig, MockIssuer};
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn only_local_paths() {
+        for path in &["/", "/shops/loja", "/login?next=/a//b"] {
+            assert!(local_path(path), "{}", path);
+        }
+        for path in &[
+            "",
+            "shops",
+            "https://evil.com",
+            "//evil.com",
+            "/\\evil.com",
+            "/\\/evil.com",
+            "/a\\b",
+            "/\t/evil.com",
+            "/\n",
+        ] {
+            assert!(!local_path(path), "{:?}", path);
+        }
+    }
+
+    /// Começa um login pelo emissor de teste, retornando o redirecionamento guardado no cookie
+    async fn login_redirect(redirect: &str) -> Option<String> {
+        let issuer = MockIssuer::new(MockConfig {
+            redirect_url: "http://localhost/oidc/callback".into(),
+            email: "a@b.com".into(),
+            email_verified: true,
+            name: "Mock".into(),
+        });
+        let providers: Providers = Some(Box::new(issuer));
+        let rocket = rocket::build()
+            .manage(providers)
+            .mount("/oidc", rocket::routes![login]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get(format!("/oidc/login?redirect={}", redirect))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with("http://localhost/oidc/callback?state="));
+        let cookie = client.cookies().get_private(FLOW_COOKIE).unwrap();
+        let flow: Flow = serde_json::from_str(cookie.value()).unwrap();
+        flow.redirect
+    }
+
+    #[rocket::async_test]
+    async fn login_keeps_only_local_redirects() {
+        assert_eq!(login_redirect("/shops").await.as_deref(), Some("/shops"));
+        assert_eq!(login_redirect("%2F%5Cevil.com").await, None);
+        assert_eq!(login_redirect("%2F%2Fevil.com").await, None);
+        assert_eq!(login_redirect("https%3A%2F%2Fevil.com").await, None);
+    }
+}
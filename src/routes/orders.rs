@@ -0,0 +1,143 @@
+use crate::guards::{AdminUser, AuthenticatedUser, Principal};
+use crate::payment::Payments;
+use crate::routes::purchases::check_purchaser;
+use crate::routes::query::PageQuery;
+use crate::schema::{Order, OrderFilter, OrderLine, Page, Permission, Shop, User};
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::{get, post, State};
+use serde::Deserialize;
+
+/// Lê um pedido. O comprador e os administradores veem o pedido inteiro, e quem vê as vendas de
+/// uma loja do pedido vê apenas os itens dela
+#[get("/<id>")]
+async fn read(db: Database, id: Uuid, requester: Result<Principal>) -> Result<Json<Order>> {
+    let requester = requester?;
+    let order = Order::read(&db, id).await?;
+
+    let mut denied = match &requester {
+        Principal::User(user) => match check_purchaser(user, order.purchaser.as_deref()) {
+            Ok(()) => return Ok(Json(order)),
+            Err(e) => e,
+        },
+        Principal::ApiKey(_) => Permission::ViewSales.denied(),
+    };
+    let mut shops = Vec::new();
+    for slug in order.shops(&db).await? {
+        let shop = Shop::read(&db, &slug).await?;
+        match requester
+            .check_permission(&db, &shop, Permission::ViewSales)
+            .await
+        {
+            Ok(()) => shops.push(shop.slug),
+            Err(e) => denied = e,
+        }
+    }
+    if shops.is_empty() {
+        return Err(denied);
+    }
+    let order = Order::read_from_shops(&db, id, shops).await?;
+    Ok(Json(order))
+}
+
+/// Lista os pedidos de um usuário, paginados
+#[get("/?<purchaser>&<page..>")]
+async fn list_by_purchaser(
+    db: Database,
+    purchaser: String,
+    page: PageQuery,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Page<Order>>> {
+    let requester = requester?;
+    let target = User::read(&db, &purchaser).await?;
+
+    check_purchaser(&requester, Some(&target.email))?;
+    let filter = OrderFilter {
+        purchaser: Some(target.email),
+        ..Default::default()
+    };
+    let orders = Order::list(&db, filter, page.page()?).await?;
+    Ok(Json(orders))
+}
+
+/// Lista os pedidos com produtos de uma loja, paginados. Cada um mostra apenas os itens da loja
+#[get("/?<shop>&<page..>", rank = 2)]
+async fn list_by_shop(
+    db: Database,
+    shop: String,
+    page: PageQuery,
+    requester: Result<Principal>,
+) -> Result<Json<Page<Order>>> {
+    let requester = requester?;
+    let target = Shop::read(&db, &shop).await?;
+
+    requester
+        .check_permission(&db, &target, Permission::ViewSales)
+        .await?;
+    let filter = OrderFilter {
+        shop: Some(target.slug),
+        ..Default::default()
+    };
+    let orders = Order::list(&db, filter, page.page()?).await?;
+    Ok(Json(orders))
+}
+
+/// Lista todos os pedidos, paginados, dos mais recentes para os mais antigos
+#[get("/?<page..>", rank = 100)]
+async fn list(
+    db: Database,
+    admin: Result<AdminUser>,
+    page: PageQuery,
+) -> Result<Json<Page<Order>>> {
+    admin?;
+    let orders = Order::list(&db, OrderFilter::default(), page.page()?).await?;
+    Ok(Json(orders))
+}
+
+#[derive(Deserialize, Debug)]
+struct OrderItem {
+    product: String,
+    /// Obrigatória para produtos com variantes
+    variant: Option<Uuid>,
+    amount: i32,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrderRequest {
+    items: Vec<OrderItem>,
+}
+
+#[post("/", data = "<body>")]
+async fn create(
+    db: Database,
+    payments: &State<Payments>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, OrderRequest>,
+) -> Result<status::Created<Json<Order>>> {
+    let requester = requester?;
+    let body = body?.into_inner();
+    requester.check_verified()?;
+
+    let lines = body
+        .items
+        .into_iter()
+        .map(|item| OrderLine {
+            product: item.product,
+            variant: item.variant,
+            amount: item.amount,
+        })
+        .collect();
+
+    let mut order = Order::create(&db, &requester, lines, &requester).await?;
+    order.pay(&db, payments.as_ref(), &requester).await?;
+    Ok(
+        status::Created::new(format!("https://cincobola.misterio.me/orders/{}", order.id))
+            .body(Json(order)),
+    )
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![read, list, list_by_purchaser, list_by_shop, create]
+}
@@ -1,26 +1,81 @@
-use crate::schema::{Product, Shop, User, UserToken};
+use crate::guards::Principal;
+use crate::routes::query::{parse_decimal, PageQuery};
+use crate::routes::upload::{image_file, ImageUpload};
+use crate::schema::{
+    Category, Page, Permission, Product, ProductFilter, SearchResult, Shop, Tag, Variant,
+};
+use crate::storage::Uploads;
 use crate::{BodyResult, Database, Error, Result};
-use futures::try_join;
+use rocket::form::FromForm;
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::json::Json;
-use rocket::{delete, get, patch, post};
+use rocket::{delete, get, patch, post, put, State};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-#[get("/?<shop>")]
-async fn list_by_shop(db: Database, shop: String) -> Result<Json<Vec<Product>>> {
-    let shop = Shop::read(&db, &shop).await?;
-    let products = Product::list_from_shop(&db, &shop).await?;
-    Ok(Json(products))
+/// Filtros, paginação e ordenação da listagem de produtos
+#[derive(Debug, FromForm)]
+struct ListQuery {
+    shop: Option<String>,
+    min_price: Option<String>,
+    max_price: Option<String>,
+    in_stock: Option<bool>,
+    /// Inclui as subcategorias
+    category: Option<String>,
+    tag: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort: Option<String>,
+    order: Option<String>,
 }
 
-#[get("/")]
-async fn list(db: Database) -> Result<Json<Vec<Product>>> {
-    let products = Product::list(&db).await?;
+/// Lista os produtos, paginados. Filtros: `shop`, `min_price`, `max_price`, `in_stock`,
+/// `category` e `tag`; ordenação (`sort`) por `slug`, `name`, `price` ou `sold`
+#[get("/?<query..>")]
+async fn list(db: Database, query: ListQuery) -> Result<Json<Page<Product>>> {
+    // Uma loja ou categoria inexistente é um erro, e não uma lista vazia
+    if let Some(shop) = &query.shop {
+        Shop::read(&db, shop).await?;
+    }
+    if let Some(category) = &query.category {
+        Category::read(&db, category).await?;
+    }
+    let page = PageQuery {
+        limit: query.limit,
+        offset: query.offset,
+        sort: query.sort,
+        order: query.order,
+    };
+    let filter = ProductFilter {
+        shop: query.shop,
+        min_price: parse_decimal(query.min_price)?,
+        max_price: parse_decimal(query.max_price)?,
+        in_stock: query.in_stock.unwrap_or(false),
+        category: query.category,
+        tag: query.tag,
+    };
+    let products = Product::list(&db, filter, page.sort()?, page.page()?).await?;
     Ok(Json(products))
 }
 
+/// Busca produtos pelo nome e pela descrição (`q`), opcionalmente numa loja (`shop`). Os
+/// resultados vêm paginados, dos mais para os menos relevantes
+#[get("/search?<q>&<shop>&<page..>")]
+async fn search(
+    db: Database,
+    q: Option<String>,
+    shop: Option<String>,
+    page: PageQuery,
+) -> Result<Json<Page<SearchResult>>> {
+    if let Some(shop) = &shop {
+        Shop::read(&db, shop).await?;
+    }
+    let results =
+        Product::search(&db, q.as_deref().unwrap_or_default(), shop, page.page()?).await?;
+    Ok(Json(results))
+}
+
 #[get("/<slug>")]
 async fn read(db: Database, slug: String) -> Result<Json<Product>> {
     let product = Product::read(&db, &slug).await?;
@@ -37,17 +92,24 @@ struct CreateRequest {
     sold: i32,
     details: String,
     picture: String,
+    /// Eixos das variantes, como `["tamanho", "cor"]`
+    #[serde(default)]
+    options: Vec<String>,
 }
 
 #[post("/", data = "<body>")]
 async fn create(
     db: Database,
-    token: Result<UserToken>,
+    requester: Result<Principal>,
     body: BodyResult<'_, CreateRequest>,
 ) -> Result<status::Created<Json<Product>>> {
     let body = body?.into_inner();
-    let requester = User::read_from_token(&db, &token?).await?;
-    let requester_shops = Shop::list_from_user(&db, &requester).await?;
+    let requester = requester?;
+    let shop = Shop::read(&db, &body.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+
     let product = Product {
         slug: body.slug,
         shop: body.shop,
@@ -57,20 +119,11 @@ async fn create(
         sold: body.sold,
         details: body.details,
         picture: body.picture,
+        thumbnail: None,
+        options: body.options,
+        uploads: Vec::new(),
     };
-    // Caso esse usuário não tenha permissão de adicionar produtos à essa loja
-    if !requester_shops
-        .iter()
-        .any(|shop| shop.slug == product.shop)
-        && !requester.admin
-    {
-        return Err(Error::builder()
-            .code(Status::Unauthorized)
-            .description("Você não tem permissão para adicionar produtos à essa loja")
-            .build());
-    }
-
-    product.create(&db).await?;
+    product.create(&db, &requester).await?;
     Ok(status::Created::new(format!(
         "https://cincobola.misterio.me/products/{}",
         product.slug
@@ -88,41 +141,43 @@ struct UpdateRequest {
     sold: Option<i32>,
     details: Option<String>,
     picture: Option<String>,
+    options: Option<Vec<String>>,
 }
 
 #[patch("/<slug>", data = "<body>")]
 async fn update(
     db: Database,
+    uploads: &State<Uploads>,
     slug: String,
-    token: Result<UserToken>,
+    requester: Result<Principal>,
     body: BodyResult<'_, UpdateRequest>,
 ) -> Result<Json<Product>> {
     let body = body?.into_inner();
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let product = Product::read(&db, &slug);
-    let (requester, mut product) = try_join!(requester, product)?;
-    let requester_shops = Shop::list_from_user(&db, &requester).await?;
-
-    if !requester_shops
-        .iter()
-        .any(|shop| shop.slug == product.shop)
-        && !requester.admin
+    let requester = requester?;
+    let mut product = Product::read(&db, &slug).await?;
+    let shop = Shop::read(&db, &product.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+
+    // O estoque e os eixos de um produto com variantes dependem delas
+    let has_variants = !Variant::list_from_product(&db, &product).await?.is_empty();
+    if has_variants
+        && (body.available.is_some()
+            || body.sold.is_some()
+            || body.options.as_ref().is_some_and(|x| *x != product.options))
     {
         return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para modificar esse produto")
+            .code(Status::Conflict)
+            .description("Esse produto tem variantes: altere o estoque e as opções por elas")
             .build());
     }
 
-    let old_slug = product.slug.clone();
+    let before = product.clone();
     // Adicionar campos
     if let Some(x) = body.slug {
         product.slug = x;
     }
-    if let Some(x) = body.shop {
-        product.shop = x;
-    }
     if let Some(x) = body.name {
         product.name = x;
     }
@@ -139,46 +194,150 @@ async fn update(
         product.details = x;
     }
     if let Some(x) = body.picture {
+        // A miniatura e os arquivos são apenas de imagens enviadas
+        if x != product.picture {
+            product.thumbnail = None;
+            product.uploads = Vec::new();
+        }
         product.picture = x;
     }
+    if let Some(x) = body.options {
+        product.options = x;
+    }
 
-    if !requester_shops
-        .iter()
-        .any(|shop| shop.slug == product.shop)
-        && !requester.admin
-    {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para mover o produto à uma loja que não é sua")
-            .build());
+    // Mover o produto exige permissão também na loja de destino
+    if let Some(x) = body.shop {
+        let shop = Shop::read(&db, &x).await?;
+        requester
+            .check_permission(&db, &shop, Permission::EditProducts)
+            .await?;
+        product.shop = x;
     }
 
-    product.update(&db, &old_slug).await?;
+    product.update(&db, &before, &requester).await?;
+    if product.uploads != before.uploads {
+        uploads.discard(&before.uploads).await;
+    }
     Ok(Json(product))
 }
 
 #[delete("/<slug>")]
-async fn delete(db: Database, slug: String, token: Result<UserToken>) -> Result<status::NoContent> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let product = Product::read(&db, &slug);
-    let (requester, product) = try_join!(requester, product)?;
-    let requester_shops = Shop::list_from_user(&db, &requester).await?;
-
-    if !requester_shops
-        .iter()
-        .any(|shop| shop.slug == product.shop)
-        && !requester.admin
-    {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para remover esse produto")
-            .build());
-    }
-    product.delete(&db).await?;
+async fn delete(
+    db: Database,
+    uploads: &State<Uploads>,
+    slug: String,
+    requester: Result<Principal>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    let product = Product::read(&db, &slug).await?;
+    let shop = Shop::read(&db, &product.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+    product.delete(&db, &requester).await?;
+    uploads.discard(&product.uploads).await;
     Ok(status::NoContent)
 }
 
+/// Envia a foto do produto, num formulário multipart (campo `file`). A foto e a miniatura ficam no
+/// armazenamento, e as anteriores são removidas
+#[post("/<slug>/picture", data = "<upload>")]
+async fn upload_picture(
+    db: Database,
+    uploads: &State<Uploads>,
+    slug: String,
+    requester: Result<Principal>,
+    upload: ImageUpload<'_>,
+) -> Result<Json<Product>> {
+    let requester = requester?;
+    let mut product = Product::read(&db, &slug).await?;
+    let shop = Shop::read(&db, &product.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+    let file = image_file(&upload)?;
+
+    let image = uploads.store_image("products", file).await?;
+    let before = product.clone();
+    product.picture = image.url;
+    product.thumbnail = Some(image.thumbnail);
+    product.uploads = image.keys;
+    if let Err(e) = product.update(&db, &before, &requester).await {
+        uploads.discard(&product.uploads).await;
+        return Err(e);
+    }
+    uploads.discard(&before.uploads).await;
+    Ok(Json(product))
+}
+
+#[get("/<slug>/categories")]
+async fn list_categories(db: Database, slug: String) -> Result<Json<Vec<Category>>> {
+    let product = Product::read(&db, &slug).await?;
+    let categories = Category::list_from_product(&db, &product).await?;
+    Ok(Json(categories))
+}
+
+/// Troca as categorias do produto pelas da lista (de identificadores)
+#[put("/<slug>/categories", data = "<body>")]
+async fn set_categories(
+    db: Database,
+    slug: String,
+    requester: Result<Principal>,
+    body: BodyResult<'_, Vec<String>>,
+) -> Result<Json<Vec<Category>>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let product = Product::read(&db, &slug).await?;
+    let shop = Shop::read(&db, &product.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+
+    Category::set_for_product(&db, &product, body).await?;
+    let categories = Category::list_from_product(&db, &product).await?;
+    Ok(Json(categories))
+}
+
+#[get("/<slug>/tags")]
+async fn list_tags(db: Database, slug: String) -> Result<Json<Vec<Tag>>> {
+    let product = Product::read(&db, &slug).await?;
+    let tags = Tag::list_from_product(&db, &product).await?;
+    Ok(Json(tags))
+}
+
+/// Troca as tags do produto pelas da lista (de identificadores de tags da loja)
+#[put("/<slug>/tags", data = "<body>")]
+async fn set_tags(
+    db: Database,
+    slug: String,
+    requester: Result<Principal>,
+    body: BodyResult<'_, Vec<String>>,
+) -> Result<Json<Vec<Tag>>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let product = Product::read(&db, &slug).await?;
+    let shop = Shop::read(&db, &product.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+
+    Tag::set_for_product(&db, &product, body).await?;
+    let tags = Tag::list_from_product(&db, &product).await?;
+    Ok(Json(tags))
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![delete, update, create, read, list, list_by_shop]
+    rocket::routes![
+        delete,
+        update,
+        create,
+        search,
+        read,
+        list,
+        list_categories,
+        set_categories,
+        list_tags,
+        set_tags,
+        upload_picture
+    ]
 }
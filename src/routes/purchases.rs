@@ -1,95 +1,168 @@
-use crate::schema::{Product, Purchase, Shop, User, UserToken};
+use crate::guards::{AdminUser, AuthenticatedUser, Principal};
+use crate::payment::{PaymentStatus, Payments};
+use crate::routes::query::{parse_time, PageQuery};
+use crate::schema::{AuditAction, Page, Permission, Product, Purchase, PurchaseFilter, Shop, User};
 use crate::{BodyResult, Database, Error, Result};
-use futures::try_join;
+use chrono::Utc;
 use rocket::http::Status;
 use rocket::serde::json::Json;
-use rocket::{get, post};
+use rocket::serde::uuid::Uuid;
+use rocket::{get, post, State};
+use rust_decimal::Decimal;
 use serde::Deserialize;
-use chrono::Utc;
-
-#[get("/?<purchaser>")]
-async fn list_by_purchaser(
-    db: Database,
-    purchaser: String,
-    token: Result<UserToken>,
-) -> Result<Json<Vec<Purchase>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = User::read(&db, &purchaser);
 
-    let (requester, target) = try_join!(requester, target)?;
-
-    if requester.email != target.email && !requester.admin {
+/// Verifica se o requisitante pode ver as compras feitas por um usuário
+pub(crate) fn check_purchaser(requester: &User, purchaser: Option<&str>) -> Result<()> {
+    if purchaser != Some(requester.email.as_str()) && !requester.admin {
         return Err(Error::builder()
             .code(Status::Forbidden)
             .description("Você não tem permissão para listar as compras desse usuário")
             .build());
     }
+    Ok(())
+}
+
+/// Verifica se o requisitante pode ver as compras feitas numa loja
+async fn check_shop(db: &Database, requester: &User, shop: &Shop) -> Result<()> {
+    shop.check_permission(db, requester, Permission::ViewSales)
+        .await
+}
+
+/// Verifica se o requisitante pode cancelar ou reembolsar a compra: apenas os donos da loja do
+/// produto ou um administrador
+async fn check_refund(db: &Database, requester: &User, purchase: &Purchase) -> Result<()> {
+    if requester.admin {
+        return Ok(());
+    }
+    match &purchase.product {
+        Some(product) => {
+            Shop::read(db, &Product::read(db, product).await?.shop)
+                .await?
+                .check_permission(db, requester, Permission::Refund)
+                .await
+        }
+        None => Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Você não tem permissão para reembolsar essa compra")
+            .build()),
+    }
+}
+
+#[get("/<id>")]
+async fn read(
+    db: Database,
+    id: Uuid,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Purchase>> {
+    let requester = requester?;
+    let purchase = Purchase::read(&db, id).await?;
+
+    // Além do próprio comprador, o gerente da loja do produto também pode ver a compra
+    if let Err(e) = check_purchaser(&requester, purchase.purchaser.as_deref()) {
+        let product = match &purchase.product {
+            Some(product) => Product::read(&db, product).await?,
+            None => return Err(e),
+        };
+        let shop = Shop::read(&db, &product.shop).await?;
+        check_shop(&db, &requester, &shop).await?;
+    }
+    Ok(Json(purchase))
+}
 
-    let purchases = Purchase::list_from_user(&db, &target);
+/// Lista as compras de um usuário, paginadas
+#[get("/?<purchaser>&<since>&<until>&<page..>")]
+async fn list_by_purchaser(
+    db: Database,
+    purchaser: String,
+    since: Option<String>,
+    until: Option<String>,
+    page: PageQuery,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Page<Purchase>>> {
+    let requester = requester?;
+    let target = User::read(&db, &purchaser).await?;
+    check_purchaser(&requester, Some(&target.email))?;
+
+    let filter = PurchaseFilter {
+        purchaser: Some(target.email),
+        since: parse_time(since)?,
+        until: parse_time(until)?,
+        ..Default::default()
+    };
+    let purchases = Purchase::list(&db, filter, page.sort()?, page.page()?);
     Ok(Json(purchases.await?))
 }
 
-#[get("/?<product>", rank = 2)]
+/// Lista as compras de um produto, paginadas
+#[get("/?<product>&<since>&<until>&<page..>", rank = 2)]
 async fn list_by_product(
     db: Database,
     product: String,
-    token: Result<UserToken>,
-) -> Result<Json<Vec<Purchase>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = Product::read(&db, &product);
-
-    let (requester, target) = try_join!(requester, target)?;
-    let requester_shops = Shop::list_from_user(&db, &requester).await?;
-
-    if !requester_shops
-        .iter()
-        .any(|shop| shop.slug == target.shop)
-        && !requester.admin
-    {
-        return Err(Error::builder()
-            .code(Status::Unauthorized)
-            .description("Você não tem permissão para listar compras desse produto")
-            .build());
-    }
-    let purchases = Purchase::list_from_product(&db, &target).await?;
+    since: Option<String>,
+    until: Option<String>,
+    page: PageQuery,
+    requester: Result<Principal>,
+) -> Result<Json<Page<Purchase>>> {
+    let requester = requester?;
+    let target = Product::read(&db, &product).await?;
+    let shop = Shop::read(&db, &target.shop).await?;
+    requester
+        .check_permission(&db, &shop, Permission::ViewSales)
+        .await?;
+
+    let filter = PurchaseFilter {
+        product: Some(target.slug),
+        since: parse_time(since)?,
+        until: parse_time(until)?,
+        ..Default::default()
+    };
+    let purchases = Purchase::list(&db, filter, page.sort()?, page.page()?).await?;
     Ok(Json(purchases))
 }
 
-#[get("/?<shop>", rank = 3)]
+/// Lista as compras dos produtos de uma loja, paginadas
+#[get("/?<shop>&<since>&<until>&<page..>", rank = 3)]
 async fn list_by_shop(
     db: Database,
     shop: String,
-    token: Result<UserToken>,
-) -> Result<Json<Vec<Purchase>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = Shop::read(&db, &shop);
-
-    let (requester, target) = try_join!(requester, target)?;
-    if requester.email != target.manager && !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Unauthorized)
-            .description("Você não tem permissão para listar compras dessa loja")
-            .build());
-    }
+    since: Option<String>,
+    until: Option<String>,
+    page: PageQuery,
+    requester: Result<Principal>,
+) -> Result<Json<Page<Purchase>>> {
+    let requester = requester?;
+    let target = Shop::read(&db, &shop).await?;
+    requester
+        .check_permission(&db, &target, Permission::ViewSales)
+        .await?;
 
-    let purchases = Purchase::list_from_shop(&db, &target).await?;
+    let filter = PurchaseFilter {
+        shop: Some(target.slug),
+        since: parse_time(since)?,
+        until: parse_time(until)?,
+        ..Default::default()
+    };
+    let purchases = Purchase::list(&db, filter, page.sort()?, page.page()?).await?;
     Ok(Json(purchases))
 }
 
-#[get("/", rank = 100)]
-async fn list(db: Database, token: Result<UserToken>) -> Result<Json<Vec<Purchase>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token).await?;
-    if !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para listar todas as compras")
-            .build());
-    }
-    let purchases = Purchase::list(&db).await?;
+/// Lista todas as compras, paginadas. Por padrão das mais recentes para as mais antigas;
+/// ordenação (`sort`) por `time`, `paid` ou `amount`
+#[get("/?<since>&<until>&<page..>", rank = 100)]
+async fn list(
+    db: Database,
+    admin: Result<AdminUser>,
+    since: Option<String>,
+    until: Option<String>,
+    page: PageQuery,
+) -> Result<Json<Page<Purchase>>> {
+    admin?;
+    let filter = PurchaseFilter {
+        since: parse_time(since)?,
+        until: parse_time(until)?,
+        ..Default::default()
+    };
+    let purchases = Purchase::list(&db, filter, page.sort()?, page.page()?).await?;
     Ok(Json(purchases))
 }
 
@@ -97,30 +170,123 @@ async fn list(db: Database, token: Result<UserToken>) -> Result<Json<Vec<Purchas
 struct BuyRequest {
     amount: i32,
     product: String,
+    /// Obrigatória para produtos com variantes
+    variant: Option<Uuid>,
 }
 
 #[post("/", data = "<body>")]
-async fn create(db: Database, token: Result<UserToken>, body: BodyResult<'_, BuyRequest>) -> Result<Json<Purchase>> {
-    let token = token?;
+async fn create(
+    db: Database,
+    payments: &State<Payments>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, BuyRequest>,
+) -> Result<Json<Purchase>> {
+    let requester = requester?;
     let body = body?.into_inner();
 
-    let (requester, product) = try_join!(User::read_from_token(&db, &token),Product::read(&db, &body.product))?;
+    requester.check_verified()?;
+    let product = Product::read(&db, &body.product).await?;
 
-    let purchase = Purchase {
+    let mut purchase = Purchase {
+        id: Uuid::new_v4(),
         amount: body.amount,
         product: Some(product.slug),
-        purchaser: Some(requester.email),
+        variant: body.variant,
+        purchaser: Some(requester.email.clone()),
         paid: product.price,
-        time: Utc::now()
+        time: Utc::now(),
+        order: None,
+        payment_status: PaymentStatus::Pending,
+        payment_reference: None,
+        refunded: 0,
+        refund: Decimal::ZERO,
+        refund_reason: None,
     };
-    
-    // Aqui a gente cobraria a pessoa
 
-    purchase.create(&db).await?;
+    // Verifica e desconta o estoque junto com o registro da compra
+    purchase.create(&db, &requester).await?;
+    // Só então cobra a pessoa
+    purchase.pay(&db, payments.as_ref(), &requester).await?;
+
+    Ok(Json(purchase))
+}
+
+#[derive(Deserialize, Debug)]
+struct RefundRequest {
+    /// Quantas unidades reembolsar. Caso omitido, todas que ainda não foram
+    amount: Option<i32>,
+    reason: String,
+}
+
+#[post("/<id>/refund", data = "<body>")]
+async fn refund(
+    db: Database,
+    payments: &State<Payments>,
+    id: Uuid,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, RefundRequest>,
+) -> Result<Json<Purchase>> {
+    let requester = requester?;
+    let body = body?.into_inner();
+    let mut purchase = Purchase::read(&db, id).await?;
+    check_refund(&db, &requester, &purchase).await?;
+
+    let amount = body.amount.unwrap_or(purchase.amount - purchase.refunded);
+    purchase
+        .refund(
+            &db,
+            payments.as_ref(),
+            amount,
+            &body.reason,
+            AuditAction::Refund,
+            &requester,
+        )
+        .await?;
+    Ok(Json(purchase))
+}
+
+#[derive(Deserialize, Debug)]
+struct CancelRequest {
+    reason: String,
+}
+
+#[post("/<id>/cancel", data = "<body>")]
+async fn cancel(
+    db: Database,
+    payments: &State<Payments>,
+    id: Uuid,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, CancelRequest>,
+) -> Result<Json<Purchase>> {
+    let requester = requester?;
+    let body = body?.into_inner();
+    let mut purchase = Purchase::read(&db, id).await?;
+    check_refund(&db, &requester, &purchase).await?;
 
+    // Cancelar é reembolsar tudo que ainda não foi
+    let amount = purchase.amount - purchase.refunded;
+    purchase
+        .refund(
+            &db,
+            payments.as_ref(),
+            amount,
+            &body.reason,
+            AuditAction::Cancel,
+            &requester,
+        )
+        .await?;
     Ok(Json(purchase))
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![list, list_by_shop, list_by_purchaser, list_by_product, create]
+    rocket::routes![
+        read,
+        list,
+        list_by_shop,
+        list_by_purchaser,
+        list_by_product,
+        create,
+        refund,
+        cancel
+    ]
 }
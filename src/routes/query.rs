@@ -0,0 +1,59 @@
+//! Parâmetros de query string compartilhados pelas listagens
+use crate::schema::{PageRequest, Sort, SortColumn};
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use rocket::form::FromForm;
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Paginação e ordenação: `?limit=20&offset=40&sort=price&order=desc`
+#[derive(Debug, Default, FromForm)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Coluna pela qual ordenar
+    pub sort: Option<String>,
+    /// `asc` ou `desc`
+    pub order: Option<String>,
+}
+
+impl PageQuery {
+    pub fn page(&self) -> Result<PageRequest> {
+        PageRequest::new(self.limit, self.offset)
+    }
+    pub fn sort<C: SortColumn>(&self) -> Result<Sort<C>> {
+        Sort::new(self.sort.as_deref(), self.order.as_deref())
+    }
+}
+
+/// Lê um horário em RFC 3339, como `2021-10-18T12:00:00Z`
+pub fn parse_time(time: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    time.map(|time| {
+        DateTime::parse_from_rfc3339(&time)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| {
+                Error::builder()
+                    .source(Box::new(e))
+                    .code(Status::BadRequest)
+                    .description("Horário inválido, use o formato RFC 3339")
+                    .build()
+            })
+    })
+    .transpose()
+}
+
+/// Lê um valor decimal, como `10.50`
+pub fn parse_decimal(value: Option<String>) -> Result<Option<Decimal>> {
+    value
+        .map(|value| {
+            Decimal::from_str(&value).map_err(|e| {
+                Error::builder()
+                    .source(Box::new(e))
+                    .code(Status::BadRequest)
+                    .description("Valor inválido, use um número como 10.50")
+                    .build()
+            })
+        })
+        .transpose()
+}
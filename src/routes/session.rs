@@ -1,40 +1,113 @@
-use crate::schema::{User, UserToken};
+use crate::guards::AuthenticatedUser;
+use crate::routes::query::PageQuery;
+use crate::schema::{ClientInfo, LoginAttempt, Page, Session, User, UserToken};
+use crate::token::TokenKey;
 use crate::{BodyResult, Database, Error, Result};
 
 use rocket::http::Status;
+use rocket::response::status;
 use rocket::serde::json::Json;
-use rocket::{delete, post};
-use serde::Deserialize;
+use rocket::serde::uuid::Uuid;
+use rocket::{delete, get, post, State};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 struct LoginRequest {
     email: String,
     password: String,
+    /// Código do app autenticador (ou de recuperação), caso o usuário use dois fatores
+    code: Option<String>,
 }
 #[post("/", data = "<body>")]
-async fn login(db: Database, body: BodyResult<'_, LoginRequest>) -> Result<Json<User>> {
+async fn login(
+    db: Database,
+    key: &State<TokenKey>,
+    client: ClientInfo,
+    body: BodyResult<'_, LoginRequest>,
+) -> Result<Json<User>> {
     let body = body?.into_inner();
-    let mut user = User::read(&db, &body.email).await?;
-    user.token = Some(User::generate_token()?);
-    user.update(&db, &user.email).await?;
-    if user.verify_password(&body.password) {
-        Ok(Json(user))
-    } else {
-        Err(Error::builder()
-            .code(Status::Unauthorized)
-            .description("Senha incorreta")
-            .build())
-    }
+    let mut user = LoginAttempt::authenticate(
+        &db,
+        key,
+        &body.email,
+        &body.password,
+        body.code.as_deref(),
+        &client,
+    )
+    .await?;
+    // O token só é gerado depois da senha ser verificada
+    let session = Session::create(&db, key, &user, client).await?;
+    user.token = session.token;
+    Ok(Json(user))
 }
 
 #[delete("/")]
-async fn logout(db: Database, token: Result<UserToken>) -> Result<()> {
-    let mut user = User::read_from_token(&db, &token?).await?;
-    user.token = None;
-    user.update(&db, &user.email).await?;
+async fn logout(db: Database, key: &State<TokenKey>, token: Result<UserToken>) -> Result<()> {
+    let session = Session::read_from_token(&db, key, &token?).await?;
+    session.delete(&db).await?;
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+struct SessionInfo {
+    #[serde(flatten)]
+    session: Session,
+    /// Se essa é a sessão usada no request
+    current: bool,
+}
+
+/// Lista as sessões abertas do usuário, paginadas, das usadas mais recentemente para as mais
+/// antigas
+#[get("/?<page..>")]
+async fn list(
+    db: Database,
+    key: &State<TokenKey>,
+    page: PageQuery,
+    requester: Result<AuthenticatedUser>,
+    token: Result<UserToken>,
+) -> Result<Json<Page<SessionInfo>>> {
+    let requester = requester?;
+    let current = Session::read_from_token(&db, key, &token?).await?;
+
+    let sessions = Session::list_from_user(&db, &requester, page.page()?)
+        .await?
+        .map(|session| SessionInfo {
+            current: session.id == current.id,
+            session,
+        });
+    Ok(Json(sessions))
+}
+
+#[delete("/all")]
+async fn revoke_all(
+    db: Database,
+    requester: Result<AuthenticatedUser>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    Session::delete_from_user(&db, &requester).await?;
+    Ok(status::NoContent)
+}
+
+#[delete("/<id>")]
+async fn revoke(
+    db: Database,
+    id: Uuid,
+    requester: Result<AuthenticatedUser>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    let target = Session::read(&db, id).await?;
+
+    // Apenas um administrador ou o próprio usuário podem encerrar a sessão
+    if target.owner != requester.email && !requester.admin {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Você não tem permissão para encerrar essa sessão")
+            .build());
+    }
+    target.delete(&db).await?;
+    Ok(status::NoContent)
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![login, logout]
+    rocket::routes![login, logout, list, revoke_all, revoke]
 }
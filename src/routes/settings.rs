@@ -0,0 +1,30 @@
+use crate::guards::AdminUser;
+use crate::schema::Settings;
+use crate::{BodyResult, Database, Result};
+use rocket::serde::json::Json;
+use rocket::{get, put};
+
+#[get("/")]
+async fn read(db: Database, admin: Result<AdminUser>) -> Result<Json<Settings>> {
+    admin?;
+    let settings = Settings::read(&db).await?;
+    Ok(Json(settings))
+}
+
+/// Substitui as configurações. As omitidas voltam ao valor padrão
+#[put("/", data = "<body>")]
+async fn update(
+    db: Database,
+    admin: Result<AdminUser>,
+    body: BodyResult<'_, Settings>,
+) -> Result<Json<Settings>> {
+    let admin = admin?;
+    let settings = body?.into_inner();
+    let before = Settings::read(&db).await?;
+    settings.update(&db, &before, &admin).await?;
+    Ok(Json(settings))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![read, update]
+}
@@ -1,42 +1,41 @@
-use crate::schema::{Shop, User, UserToken};
+use crate::guards::{AuthenticatedUser, DeleteShop, EditShop, ShopManager};
+use crate::routes::query::PageQuery;
+use crate::routes::upload::{image_file, ImageUpload};
+use crate::schema::{Actor, Page, Shop, ShopFilter, User};
+use crate::storage::Uploads;
 use crate::{BodyResult, Database, Error, Result};
-use futures::try_join;
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::json::Json;
-use rocket::{delete, get, patch, post};
+use rocket::{delete, get, patch, post, State};
 use serde::Deserialize;
 
-#[get("/?<manager>")]
-async fn list_by_manager(
+/// Lista as lojas, paginadas. Listar as lojas de um gerente (`manager`) exige ser ele ou um
+/// administrador; ordenação (`sort`) por `slug` ou `name`
+#[get("/?<manager>&<page..>", format = "json")]
+async fn list(
     db: Database,
-    manager: String,
-    token: Result<UserToken>,
-) -> Result<Json<Vec<Shop>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = User::read(&db, &manager);
-
-    let (requester, target) = try_join!(requester, target)?;
-
-    if requester.email != target.email && !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para listar as lojas desse usuário")
-            .build());
+    manager: Option<String>,
+    page: PageQuery,
+    requester: Result<AuthenticatedUser>,
+) -> Result<Json<Page<Shop>>> {
+    if let Some(manager) = &manager {
+        let requester = requester?;
+        let target = User::read(&db, manager).await?;
+        if requester.email != target.email && !requester.admin {
+            return Err(Error::builder()
+                .code(Status::Forbidden)
+                .description("Você não tem permissão para listar as lojas desse usuário")
+                .build());
+        }
     }
 
-    let shops = Shop::list_from_user(&db, &target);
-    Ok(Json(shops.await?))
-}
-
-#[get("/")]
-async fn list(db: Database) -> Result<Json<Vec<Shop>>> {
-    let shops = Shop::list(&db).await?;
+    let filter = ShopFilter { manager };
+    let shops = Shop::list(&db, filter, page.sort()?, page.page()?).await?;
     Ok(Json(shops))
 }
 
-#[get("/<slug>")]
+#[get("/<slug>", format = "json")]
 async fn read(db: Database, slug: String) -> Result<Json<Shop>> {
     let shop = Shop::read(&db, &slug).await?;
     Ok(Json(shop))
@@ -55,19 +54,21 @@ struct CreateRequest {
 #[post("/", data = "<body>")]
 async fn create(
     db: Database,
-    token: Result<UserToken>,
+    requester: Result<AuthenticatedUser>,
     body: BodyResult<'_, CreateRequest>,
 ) -> Result<status::Created<Json<Shop>>> {
     let body = body?.into_inner();
-    let token = token?;
-    let requester = User::read_from_token(&db, &token).await?;
+    let requester = requester?;
+    requester.check_verified()?;
     let shop = Shop {
         slug: body.slug,
         name: body.name,
         color_dark: body.color_dark.replace("#", ""),
         color_light: body.color_light.replace("#", ""),
         logo: body.logo,
+        logo_thumbnail: None,
         manager: body.manager,
+        uploads: Vec::new(),
     };
 
     if shop.color_light.len() > 6 || shop.color_dark.len() > 6 {
@@ -85,7 +86,7 @@ async fn create(
             .build());
     }
 
-    shop.create(&db).await?;
+    shop.create(&db, &requester).await?;
     Ok(
         status::Created::new(format!("https://cincobola.misterio.me/shops/{}", shop.slug))
             .body(Json(shop)),
@@ -102,29 +103,16 @@ struct UpdateRequest {
     manager: Option<String>,
 }
 
-#[patch("/<slug>", data = "<body>")]
+#[patch("/<_>", data = "<body>")]
 async fn update(
     db: Database,
-    slug: String,
-    token: Result<UserToken>,
+    uploads: &State<Uploads>,
+    owner: Result<ShopManager<EditShop>>,
     body: BodyResult<'_, UpdateRequest>,
 ) -> Result<Json<Shop>> {
     let body = body?.into_inner();
-    let token = token?;
-
-    let requester = User::read_from_token(&db, &token);
-    let shop = Shop::read(&db, &slug);
-
-    let (mut shop, requester) = try_join!(shop, requester)?;
-    let old_slug = shop.slug.clone();
-
-    // Retornar erro caso o usuário esteja alterando uma loja que não é dele
-    if requester.email != shop.manager && !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para modificar essa loja")
-            .build());
-    }
+    let owner = owner?;
+    let mut shop = owner.shop.clone();
 
     // Adicionar campos
     if let Some(x) = body.slug {
@@ -140,41 +128,83 @@ async fn update(
         shop.color_light = x.replace("#", "");
     }
     if let Some(x) = body.logo {
+        // A miniatura e os arquivos são apenas de imagens enviadas
+        if x != shop.logo {
+            shop.logo_thumbnail = None;
+            shop.uploads = Vec::new();
+        }
         shop.logo = x;
     }
     if let Some(x) = body.manager {
+        // Retornar erro caso o usuário esteja passando a loja para outra pessoa
+        if x != shop.manager && x != owner.user.email && !owner.user.admin {
+            return Err(Error::builder()
+                .code(Status::Forbidden)
+                .description("Você não tem permissão para trocar o dono de uma loja")
+                .build());
+        }
         shop.manager = x;
     }
 
-    // Retornar erro caso o usuário esteja trocando posse da loja
-    if requester.email != shop.manager && !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para trocar o dono de uma loja")
-            .build());
+    shop.update(&db, &owner.shop, &owner).await?;
+    if shop.uploads != owner.shop.uploads {
+        uploads.discard(&owner.shop.uploads).await;
     }
-
-    shop.update(&db, &old_slug).await?;
     Ok(Json(shop))
 }
 
-#[delete("/<slug>")]
-async fn delete(db: Database, slug: String, token: Result<UserToken>) -> Result<status::NoContent> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let shop = Shop::read(&db, &slug);
-    let (shop, requester) = try_join!(shop, requester)?;
+/// Envia o logo da loja, num formulário multipart (campo `file`). O logo e a miniatura ficam no
+/// armazenamento, e os anteriores são removidos
+#[post("/<_>/logo", data = "<upload>")]
+async fn upload_logo(
+    db: Database,
+    uploads: &State<Uploads>,
+    owner: Result<ShopManager<EditShop>>,
+    upload: ImageUpload<'_>,
+) -> Result<Json<Shop>> {
+    let owner = owner?;
+    let file = image_file(&upload)?;
+    let mut shop = owner.shop.clone();
+
+    let image = uploads.store_image("shops", file).await?;
+    shop.logo = image.url;
+    shop.logo_thumbnail = Some(image.thumbnail);
+    shop.uploads = image.keys;
+    if let Err(e) = shop.update(&db, &owner.shop, &owner).await {
+        uploads.discard(&shop.uploads).await;
+        return Err(e);
+    }
+    uploads.discard(&owner.shop.uploads).await;
+    Ok(Json(shop))
+}
 
-    if requester.email != shop.manager && !requester.admin {
-        return Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para remover essa loja")
-            .build());
+/// Apaga a loja, registrando também a remoção dos produtos, que são apagados junto. As imagens
+/// de ambos saem do armazenamento
+pub(crate) async fn delete_shop(
+    db: &Database,
+    uploads: &Uploads,
+    actor: Actor,
+    shop: &Shop,
+) -> Result<()> {
+    let products = shop.delete(db, actor).await?;
+    uploads.discard(&shop.uploads).await;
+    for product in &products {
+        uploads.discard(&product.uploads).await;
     }
-    shop.delete(&db).await?;
+    Ok(())
+}
+
+#[delete("/<_>")]
+async fn delete(
+    db: Database,
+    uploads: &State<Uploads>,
+    owner: Result<ShopManager<DeleteShop>>,
+) -> Result<status::NoContent> {
+    let owner = owner?;
+    delete_shop(&db, uploads, Actor::from(&owner), &owner.shop).await?;
     Ok(status::NoContent)
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![list_by_manager, list, read, create, update, delete]
+    rocket::routes![list, read, create, update, upload_logo, delete]
 }
@@ -0,0 +1,102 @@
+use crate::guards::Principal;
+use crate::schema::{Permission, Shop, Tag};
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, patch, post};
+use serde::Deserialize;
+
+#[get("/<slug>/tags")]
+async fn list(db: Database, slug: String) -> Result<Json<Vec<Tag>>> {
+    let shop = Shop::read(&db, &slug).await?;
+    let tags = Tag::list_from_shop(&db, &shop).await?;
+    Ok(Json(tags))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    slug: String,
+    name: String,
+}
+
+#[post("/<slug>/tags", data = "<body>")]
+async fn create(
+    db: Database,
+    slug: String,
+    requester: Result<Principal>,
+    body: BodyResult<'_, CreateRequest>,
+) -> Result<status::Created<Json<Tag>>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let shop = Shop::read(&db, &slug).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+
+    let tag = Tag {
+        shop: shop.slug,
+        slug: body.slug,
+        name: body.name,
+    };
+    tag.create(&db).await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/shops/{}/tags/{}",
+        tag.shop, tag.slug
+    ))
+    .body(Json(tag)))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateRequest {
+    slug: Option<String>,
+    name: Option<String>,
+}
+
+#[patch("/<slug>/tags/<tag>", data = "<body>")]
+async fn update(
+    db: Database,
+    slug: String,
+    tag: String,
+    requester: Result<Principal>,
+    body: BodyResult<'_, UpdateRequest>,
+) -> Result<Json<Tag>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let shop = Shop::read(&db, &slug).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+    let mut tag = Tag::read(&db, &shop, &tag).await?;
+
+    let old_slug = tag.slug.clone();
+    if let Some(x) = body.slug {
+        tag.slug = x;
+    }
+    if let Some(x) = body.name {
+        tag.name = x;
+    }
+    tag.update(&db, &old_slug).await?;
+    Ok(Json(tag))
+}
+
+/// Remove a tag, que também sai dos produtos
+#[delete("/<slug>/tags/<tag>")]
+async fn delete(
+    db: Database,
+    slug: String,
+    tag: String,
+    requester: Result<Principal>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    let shop = Shop::read(&db, &slug).await?;
+    requester
+        .check_permission(&db, &shop, Permission::EditProducts)
+        .await?;
+    let tag = Tag::read(&db, &shop, &tag).await?;
+    tag.delete(&db).await?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list, create, update, delete]
+}
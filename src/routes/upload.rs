@@ -0,0 +1,40 @@
+use crate::{Error, Result};
+use rocket::data::ByteUnit;
+use rocket::form::{self, error::ErrorKind, Form, FromForm};
+use rocket::fs::TempFile;
+use rocket::http::Status;
+
+/// Formulário multipart com uma imagem no campo `file`
+#[derive(Debug, FromForm)]
+pub struct ImageForm<'r> {
+    pub file: TempFile<'r>,
+}
+
+/// Corpo de um envio de imagem, com os erros do formulário para serem tratados pela rota
+pub type ImageUpload<'r> = Form<form::Result<'r, ImageForm<'r>>>;
+
+/// Arquivo enviado. Arquivos acima do limite `file` da configuração são recusados
+pub fn image_file<'a, 'r>(upload: &'a ImageUpload<'r>) -> Result<&'a TempFile<'r>> {
+    match &**upload {
+        Ok(form) => Ok(&form.file),
+        Err(errors) => {
+            let max = errors.iter().find_map(|e| match e.kind {
+                ErrorKind::InvalidLength { max: Some(max), .. } => Some(max),
+                _ => None,
+            });
+            Err(match max {
+                Some(max) => Error::builder()
+                    .code(Status::PayloadTooLarge)
+                    .description(&format!(
+                        "A imagem deve ter no máximo {}",
+                        ByteUnit::from(max)
+                    ))
+                    .build(),
+                None => Error::builder()
+                    .code(Status::BadRequest)
+                    .description("Envie a imagem no campo 'file' de um formulário multipart")
+                    .build(),
+            })
+        }
+    }
+}
@@ -1,33 +1,43 @@
-use crate::schema::{User, UserToken};
+use crate::guards::{AdminUser, AuthenticatedUser};
+use crate::mail::{Mail, Mailers};
+use crate::routes::query::PageQuery;
+use crate::routes::shops::delete_shop;
+use crate::schema::{
+    Actor, ClientInfo, EmailVerification, Page, PasswordReset, Session, Shop, TwoFactor, User,
+    UserFilter,
+};
+use crate::storage::Uploads;
+use crate::token::TokenKey;
 use crate::{BodyResult, Database, Error, Result};
-use futures::try_join;
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::json::Json;
-use rocket::{delete, get, patch, post};
-use serde::Deserialize;
-
-#[get("/")]
-async fn list(db: Database, token: Result<UserToken>) -> Result<Json<Vec<User>>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token).await?;
-    if requester.admin {
-        let users = User::list(&db).await?;
-        Ok(Json(users))
-    } else {
-        Err(Error::builder()
-            .code(Status::Forbidden)
-            .description("Você não tem permissão para listar os usuários")
-            .build())
-    }
+use rocket::{delete, get, patch, post, State};
+use serde::{Deserialize, Serialize};
+
+/// Lista os usuários, paginados. Filtros: `admin` e `verified`; ordenação (`sort`) por `email`
+/// ou `name`
+#[get("/?<admin>&<verified>&<page..>")]
+async fn list(
+    db: Database,
+    requester: Result<AdminUser>,
+    admin: Option<bool>,
+    verified: Option<bool>,
+    page: PageQuery,
+) -> Result<Json<Page<User>>> {
+    requester?;
+    let filter = UserFilter { admin, verified };
+    let users = User::list(&db, filter, page.sort()?, page.page()?).await?;
+    Ok(Json(users))
 }
 #[get("/<email>")]
-async fn read(db: Database, token: Result<UserToken>, email: String) -> Result<Json<User>> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = User::read(&db, &email);
-
-    let (requester, target) = try_join!(requester, target)?;
+async fn read(
+    db: Database,
+    requester: Result<AuthenticatedUser>,
+    email: String,
+) -> Result<Json<User>> {
+    let requester = requester?;
+    let target = User::read(&db, &email).await?;
     if requester.email != target.email && !requester.admin {
         return Err(Error::builder()
             .code(Status::Forbidden)
@@ -42,23 +52,34 @@ struct RegisterRequest {
     email: String,
     password: String,
     name: String,
+    cpf: Option<String>,
 }
 
 #[post("/", data = "<body>")]
 async fn create(
     db: Database,
+    mailer: &State<Mailers>,
+    key: &State<TokenKey>,
+    client: ClientInfo,
     body: BodyResult<'_, RegisterRequest>,
 ) -> Result<status::Created<Json<User>>> {
     let body = body?.into_inner();
-    let user = User {
+    let mut user = User {
         email: body.email,
         password: User::hash_password(&body.password)?,
         name: body.name,
-        token: Some(User::generate_token()?),
+        token: None,
         admin: false,
+        verified: false,
+        cpf: match body.cpf {
+            Some(cpf) => User::parse_cpf(&cpf)?,
+            None => None,
+        },
     };
 
     user.create(&db).await?;
+    EmailVerification::send(&db, key, mailer.as_ref(), &user).await?;
+    user.token = Session::create(&db, key, &user, client).await?.token;
 
     Ok(status::Created::new(format!(
         "https://cincobola.misterio.me/users/{}",
@@ -77,17 +98,16 @@ struct UpdateRequest {
 #[patch("/<email>", data = "<body>")]
 async fn update(
     db: Database,
-    token: Result<UserToken>,
+    key: &State<TokenKey>,
+    mailer: &State<Mailers>,
+    requester: Result<AuthenticatedUser>,
     body: BodyResult<'_, UpdateRequest>,
     email: String,
 ) -> Result<Json<User>> {
     let body = body?.into_inner();
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = User::read(&db, &email);
-
-    let (requester, mut target) = try_join!(requester, target)?;
-    let old_email = target.email.clone();
+    let requester = requester?;
+    let mut target = User::read(&db, &email).await?;
+    let before = target.clone();
 
     // Apenas um administrador ou o próprio usuário podem mudar as informações
     if target.email != requester.email && !requester.admin {
@@ -98,6 +118,10 @@ async fn update(
     }
 
     if let Some(x) = body.email {
+        // Um email novo precisa ser confirmado novamente
+        if x != target.email {
+            target.verified = false;
+        }
         target.email = x;
     }
     if let Some(x) = body.password {
@@ -109,21 +133,56 @@ async fn update(
     if let Some(x) = body.admin {
         target.admin = x && requester.admin;
     }
-    target.update(&db, &old_email).await?;
+    target.update(&db, &before, &requester).await?;
+    if !target.verified {
+        EmailVerification::send(&db, key, mailer.as_ref(), &target).await?;
+    }
     Ok(Json(target))
 }
 
+/// Confirma o email, pelo link enviado a ele
+#[get("/verify?<token>")]
+async fn verify(db: Database, key: &State<TokenKey>, token: String) -> Result<Json<User>> {
+    let user = EmailVerification::consume(&db, key, &token).await?;
+    Ok(Json(user))
+}
+
+/// Envia novamente o link de confirmação
+#[post("/<email>/verification")]
+async fn resend_verification(
+    db: Database,
+    key: &State<TokenKey>,
+    mailer: &State<Mailers>,
+    requester: Result<AuthenticatedUser>,
+    email: String,
+) -> Result<status::Accepted<()>> {
+    let requester = requester?;
+    let target = User::read(&db, &email).await?;
+    if target.email != requester.email && !requester.admin {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Você não tem permissão para modificar esse usuário")
+            .build());
+    }
+    if target.verified {
+        return Err(Error::builder()
+            .code(Status::Conflict)
+            .description("Esse email já foi confirmado")
+            .build());
+    }
+    EmailVerification::send(&db, key, mailer.as_ref(), &target).await?;
+    Ok(status::Accepted(None))
+}
+
 #[delete("/<email>")]
 async fn delete(
     db: Database,
-    token: Result<UserToken>,
+    uploads: &State<Uploads>,
+    requester: Result<AuthenticatedUser>,
     email: String,
 ) -> Result<status::NoContent> {
-    let token = token?;
-    let requester = User::read_from_token(&db, &token);
-    let target = User::read(&db, &email);
-
-    let (requester, target) = try_join!(requester, target)?;
+    let requester = requester?;
+    let target = User::read(&db, &email).await?;
     // Apenas um administrador ou o próprio usuário podem apagar
     if target.email != requester.email && !requester.admin {
         return Err(Error::builder()
@@ -131,10 +190,262 @@ async fn delete(
             .description("Você não tem permissão para remover esse usuário")
             .build());
     }
-    target.delete(&db).await?;
+    // As lojas do usuário são apagadas junto com ele
+    for shop in Shop::list_from_user(&db, &target).await? {
+        delete_shop(&db, uploads, Actor::from(&requester), &shop).await?;
+    }
+    target.delete(&db, &requester).await?;
+    Ok(status::NoContent)
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetRequest {
+    email: String,
+}
+/// Envia um token de redefinição de senha para o email. A resposta é a mesma caso o usuário não
+/// exista, para não revelar quais emails estão registrados
+#[post("/password-reset", data = "<body>")]
+async fn request_reset(
+    db: Database,
+    key: &State<TokenKey>,
+    mailer: &State<Mailers>,
+    body: BodyResult<'_, ResetRequest>,
+) -> Result<status::Accepted<()>> {
+    let body = body?.into_inner();
+    let user = match User::read(&db, &body.email).await {
+        Ok(user) => user,
+        Err(_) => return Ok(status::Accepted(None)),
+    };
+    let reset = PasswordReset::create(&db, key, &user).await?;
+    mailer
+        .send(Mail {
+            to: user.email,
+            subject: "Redefinição de senha".into(),
+            body: format!(
+                "Olá, {}!\n\n\
+                Alguém (provavelmente você) pediu para redefinir a senha da sua conta.\n\
+                Use o token abaixo para escolher uma nova senha. Ele expira em {} minutos.\n\n\
+                {}\n\n\
+                Caso não tenha sido você, apenas ignore esse email.",
+                user.name,
+                PasswordReset::lifetime().num_minutes(),
+                reset.token
+            ),
+        })
+        .await?;
+    Ok(status::Accepted(None))
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetConfirmRequest {
+    token: String,
+    password: String,
+}
+/// Consome o token de redefinição e troca a senha. Todas as sessões abertas são encerradas
+#[post("/password-reset/confirm", data = "<body>")]
+async fn confirm_reset(
+    db: Database,
+    key: &State<TokenKey>,
+    body: BodyResult<'_, ResetConfirmRequest>,
+) -> Result<status::NoContent> {
+    let body = body?.into_inner();
+    let email = PasswordReset::consume(&db, key, &body.token).await?;
+    let mut user = User::read(&db, &email).await?;
+    let before = user.clone();
+    user.password = User::hash_password(&body.password)?;
+    // A senha não é registrada, nem como hash: fica apenas o fato de ter sido trocada
+    user.update(&db, &before, &before).await?;
+    Session::delete_from_user(&db, &user).await?;
+    Ok(status::NoContent)
+}
+
+/// Lê a autenticação em dois fatores do próprio usuário, que deve existir
+async fn read_own_two_factor(db: &Database, requester: &User, email: &str) -> Result<TwoFactor> {
+    if requester.email != email {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Apenas o próprio usuário pode configurar a autenticação em dois fatores")
+            .build());
+    }
+    TwoFactor::read(db, requester).await?.ok_or_else(|| {
+        Error::builder()
+            .code(Status::NotFound)
+            .description("A autenticação em dois fatores não foi configurada")
+            .build()
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct TwoFactorStatus {
+    enabled: bool,
+}
+#[get("/<email>/two-factor")]
+async fn read_two_factor(
+    db: Database,
+    requester: Result<AuthenticatedUser>,
+    email: String,
+) -> Result<Json<TwoFactorStatus>> {
+    let requester = requester?;
+    let target = User::read(&db, &email).await?;
+    if requester.email != target.email && !requester.admin {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Você não tem permissão para ver esse usuário")
+            .build());
+    }
+    let enabled = TwoFactor::is_enabled(&db, &target).await?;
+    Ok(Json(TwoFactorStatus { enabled }))
+}
+
+#[derive(Debug, Serialize)]
+struct EnrollResponse {
+    /// Segredo em base32, para ser digitado no app autenticador
+    secret: String,
+    /// URI `otpauth://`, para ser lida por QR code
+    uri: String,
+}
+/// Começa a inscrição na autenticação em dois fatores. Ela só é ativada depois de confirmada
+#[post("/<email>/two-factor")]
+async fn enroll_two_factor(
+    db: Database,
+    requester: Result<AuthenticatedUser>,
+    email: String,
+) -> Result<status::Created<Json<EnrollResponse>>> {
+    let requester = requester?;
+    if requester.email != email {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Apenas o próprio usuário pode configurar a autenticação em dois fatores")
+            .build());
+    }
+    let two_factor = TwoFactor::enroll(&db, &requester).await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/users/{}/two-factor",
+        requester.email
+    ))
+    .body(Json(EnrollResponse {
+        uri: two_factor.uri(),
+        secret: two_factor.secret,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+#[derive(Debug, Serialize)]
+struct RecoveryCodesResponse {
+    /// Códigos de uso único, para quando o app autenticador não estiver disponível. Eles não
+    /// podem ser vistos novamente
+    recovery_codes: Vec<String>,
+}
+/// Confirma a inscrição com um código do app, ativando a autenticação em dois fatores
+#[post("/<email>/two-factor/confirm", data = "<body>")]
+async fn confirm_two_factor(
+    db: Database,
+    key: &State<TokenKey>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, CodeRequest>,
+    email: String,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let mut two_factor = read_own_two_factor(&db, &requester, &email).await?;
+    let recovery_codes = two_factor.confirm(&db, key, &body.code).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Gera novos códigos de recuperação, invalidando os anteriores. Exige um código válido
+#[post("/<email>/two-factor/recovery-codes", data = "<body>")]
+async fn regenerate_recovery_codes(
+    db: Database,
+    key: &State<TokenKey>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, CodeRequest>,
+    email: String,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let two_factor = read_own_two_factor(&db, &requester, &email).await?;
+    if !two_factor.enabled || !two_factor.verify(&db, key, &body.code).await? {
+        return Err(Error::builder()
+            .code(Status::BadRequest)
+            .description("Código de autenticação inválido")
+            .build());
+    }
+    let recovery_codes = two_factor.regenerate_recovery_codes(&db, key).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Desativa a autenticação em dois fatores. Exige um código válido de quem faz o request.
+/// Administradores podem desativar a de qualquer um, com um código deles, para quando o usuário
+/// perder o app e os códigos de recuperação
+#[delete("/<email>/two-factor", data = "<body>")]
+async fn disable_two_factor(
+    db: Database,
+    key: &State<TokenKey>,
+    requester: Result<AuthenticatedUser>,
+    body: BodyResult<'_, CodeRequest>,
+    email: String,
+) -> Result<status::NoContent> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let target = User::read(&db, &email).await?;
+    if target.email != requester.email && !requester.admin {
+        return Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Você não tem permissão para modificar esse usuário")
+            .build());
+    }
+    let two_factor = match TwoFactor::read(&db, &target).await? {
+        Some(two_factor) => two_factor,
+        None => return Ok(status::NoContent),
+    };
+    // Uma inscrição ainda não confirmada pode ser cancelada sem código
+    if two_factor.enabled {
+        let own = if target.email == requester.email {
+            Some(two_factor.clone())
+        } else {
+            TwoFactor::read(&db, &requester).await?
+        };
+        match own {
+            Some(own) if own.enabled => {
+                if !own.verify(&db, key, &body.code).await? {
+                    return Err(Error::builder()
+                        .code(Status::BadRequest)
+                        .description("Código de autenticação inválido")
+                        .build());
+                }
+            }
+            _ => {
+                return Err(Error::builder()
+                    .code(Status::Forbidden)
+                    .description(
+                        "Ative a autenticação em dois fatores para desativar a de outros usuários",
+                    )
+                    .build())
+            }
+        }
+    }
+    two_factor.delete(&db, &requester).await?;
     Ok(status::NoContent)
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![list, read, create, update, delete]
+    rocket::routes![
+        list,
+        read,
+        create,
+        update,
+        delete,
+        verify,
+        resend_verification,
+        request_reset,
+        confirm_reset,
+        read_two_factor,
+        enroll_two_factor,
+        confirm_two_factor,
+        regenerate_recovery_codes,
+        disable_two_factor
+    ]
 }
@@ -0,0 +1,148 @@
+use crate::guards::Principal;
+use crate::schema::{Permission, Product, Shop, Variant};
+use crate::{BodyResult, Database, Result};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::{delete, get, patch, post};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+
+/// Lê o produto, verificando se o usuário pode editá-lo
+async fn editable_product(db: &Database, requester: &Principal, slug: &str) -> Result<Product> {
+    let product = Product::read(db, slug).await?;
+    let shop = Shop::read(db, &product.shop).await?;
+    requester
+        .check_permission(db, &shop, Permission::EditProducts)
+        .await?;
+    Ok(product)
+}
+
+#[get("/<slug>/variants")]
+async fn list(db: Database, slug: String) -> Result<Json<Vec<Variant>>> {
+    let product = Product::read(&db, &slug).await?;
+    let variants = Variant::list_from_product(&db, &product).await?;
+    Ok(Json(variants))
+}
+
+#[get("/<slug>/variants/<id>")]
+async fn read(db: Database, slug: String, id: Uuid) -> Result<Json<Variant>> {
+    let product = Product::read(&db, &slug).await?;
+    let variant = Variant::read(&db, &product, id).await?;
+    Ok(Json(variant))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    sku: String,
+    /// Um valor para cada eixo de opções do produto
+    options: BTreeMap<String, String>,
+    /// Caso omitido, vale o preço do produto
+    price: Option<Decimal>,
+    available: i32,
+    #[serde(default)]
+    sold: i32,
+}
+
+/// Cria uma variante. O estoque e as vendas do produto passam a ser a soma dos das variantes
+#[post("/<slug>/variants", data = "<body>")]
+async fn create(
+    db: Database,
+    slug: String,
+    requester: Result<Principal>,
+    body: BodyResult<'_, CreateRequest>,
+) -> Result<status::Created<Json<Variant>>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let product = editable_product(&db, &requester, &slug).await?;
+
+    let variant = Variant {
+        id: Uuid::new_v4(),
+        product: product.slug.clone(),
+        sku: body.sku,
+        options: body.options,
+        price: body.price,
+        available: body.available,
+        sold: body.sold,
+    };
+    variant.check_options(&product)?;
+    variant.create(&db, &requester).await?;
+    Ok(status::Created::new(format!(
+        "https://cincobola.misterio.me/products/{}/variants/{}",
+        variant.product, variant.id
+    ))
+    .body(Json(variant)))
+}
+
+/// Diferencia um campo omitido (`None`) de um `null` (`Some(None)`)
+fn nullable<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Option<Decimal>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateRequest {
+    sku: Option<String>,
+    options: Option<BTreeMap<String, String>>,
+    /// `null` volta a usar o preço do produto
+    #[serde(default, deserialize_with = "nullable")]
+    price: Option<Option<Decimal>>,
+    available: Option<i32>,
+    sold: Option<i32>,
+}
+
+#[patch("/<slug>/variants/<id>", data = "<body>")]
+async fn update(
+    db: Database,
+    slug: String,
+    id: Uuid,
+    requester: Result<Principal>,
+    body: BodyResult<'_, UpdateRequest>,
+) -> Result<Json<Variant>> {
+    let body = body?.into_inner();
+    let requester = requester?;
+    let product = editable_product(&db, &requester, &slug).await?;
+    let mut variant = Variant::read(&db, &product, id).await?;
+
+    let before = variant.clone();
+    if let Some(x) = body.sku {
+        variant.sku = x;
+    }
+    if let Some(x) = body.options {
+        variant.options = x;
+        variant.check_options(&product)?;
+    }
+    if let Some(x) = body.price {
+        variant.price = x;
+    }
+    if let Some(x) = body.available {
+        variant.available = x;
+    }
+    if let Some(x) = body.sold {
+        variant.sold = x;
+    }
+
+    variant.update(&db, &before, &requester).await?;
+    Ok(Json(variant))
+}
+
+/// Remove a variante, que também sai dos carrinhos
+#[delete("/<slug>/variants/<id>")]
+async fn delete(
+    db: Database,
+    slug: String,
+    id: Uuid,
+    requester: Result<Principal>,
+) -> Result<status::NoContent> {
+    let requester = requester?;
+    let product = editable_product(&db, &requester, &slug).await?;
+    let variant = Variant::read(&db, &product, id).await?;
+    variant.delete(&db, &requester).await?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list, read, create, update, delete]
+}
@@ -0,0 +1,232 @@
+use crate::schema::{Actor, AuditAction, AuditEntry, Permission, Shop, User};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Utc};
+use postgres::Row;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+/// O que uma chave de API pode fazer na loja
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ApiScope {
+    /// Ver as vendas da loja
+    #[serde(rename = "read")]
+    Read,
+    /// Adicionar, alterar e remover produtos
+    #[serde(rename = "products:write")]
+    ProductsWrite,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::ProductsWrite => "products:write",
+        }
+    }
+    /// Se esse escopo concede a permissão
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            ApiScope::Read => matches!(permission, Permission::ViewSales),
+            ApiScope::ProductsWrite => matches!(permission, Permission::EditProducts),
+        }
+    }
+}
+
+impl TryFrom<&str> for ApiScope {
+    type Error = Error;
+    fn try_from(scope: &str) -> Result<Self> {
+        match scope {
+            "read" => Ok(ApiScope::Read),
+            "products:write" => Ok(ApiScope::ProductsWrite),
+            _ => Err(Error::builder()
+                .description(&format!("Escopo desconhecido: '{}'", scope))
+                .build()),
+        }
+    }
+}
+
+/// Chave de longa duração para integrações com uma loja
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub shop: String,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// Início da chave, para que ela seja reconhecida na listagem
+    pub prefix: String,
+    /// A chave em si, presente apenas logo após ser criada. Na database só fica o hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub created_by: Option<String>,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<Row> for ApiKey {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let scopes: Vec<String> = row.try_get("scopes")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            shop: row.try_get("shop")?,
+            name: row.try_get("name")?,
+            scopes: scopes
+                .iter()
+                .map(|scope| scope.as_str().try_into())
+                .collect::<Result<_>>()?,
+            prefix: row.try_get("prefix")?,
+            key: None,
+            created_by: row.try_get("created_by")?,
+            created: row.try_get("created")?,
+            last_used: row.try_get("last_used")?,
+        })
+    }
+}
+
+impl ApiKey {
+    /// Cria uma chave nova para a loja
+    pub async fn create(
+        db: &Database,
+        key: &TokenKey,
+        shop: &Shop,
+        creator: &User,
+        name: &str,
+        scopes: Vec<ApiScope>,
+    ) -> Result<ApiKey> {
+        if scopes.is_empty() {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A chave deve ter pelo menos um escopo")
+                .build());
+        }
+        let raw = format!("cb_{}", &User::generate_token()?[..48]);
+        let hash = key.hash(&raw);
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            shop: shop.slug.clone(),
+            name: name.into(),
+            scopes,
+            prefix: raw[..10].into(),
+            key: Some(raw),
+            created_by: Some(creator.email.clone()),
+            created: Utc::now(),
+            last_used: None,
+        };
+        // A chave em si nunca vai para o registro
+        let new_key = ApiKey {
+            key: None,
+            ..api_key.clone()
+        };
+        let actor = Actor::from(creator);
+        db.run(move |db| -> Result<()> {
+            let mut tx = db.transaction()?;
+            let scopes: Vec<&str> = new_key.scopes.iter().map(ApiScope::as_str).collect();
+            tx.execute(
+                "INSERT INTO api_keys
+                (id, shop, name, scopes, prefix, key_hash, created_by, created)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &new_key.id,
+                    &new_key.shop,
+                    &new_key.name,
+                    &scopes,
+                    &new_key.prefix,
+                    &hash,
+                    &new_key.created_by,
+                    &new_key.created,
+                ],
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&new_key))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        Ok(api_key)
+    }
+    pub async fn read(db: &Database, shop: &Shop, id: Uuid) -> Result<ApiKey> {
+        let shop = shop.slug.clone();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM api_keys
+                WHERE id = $1 AND shop = $2",
+                &[&id, &shop],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Chave de API não encontrada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Dada a chave em si, busca-a e registra o uso
+    pub async fn read_from_key(db: &Database, key: &TokenKey, raw: &str) -> Result<ApiKey> {
+        let hash = key.hash(raw);
+        db.run(move |db| {
+            db.query_one(
+                "UPDATE api_keys
+                SET last_used = now()
+                WHERE key_hash = $1
+                RETURNING *",
+                &[&hash],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::Unauthorized)
+                    .description("Chave de API inválida")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    pub async fn list_from_shop(db: &Database, shop: &Shop) -> Result<Vec<ApiKey>> {
+        let shop = shop.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT *
+                FROM api_keys
+                WHERE shop = $1
+                ORDER BY created",
+                &[&shop],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(ApiKey::try_from)
+        .collect()
+    }
+    /// Revoga a chave, registrando quem a revogou
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let api_key = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM api_keys
+                WHERE id = $1",
+                &[&api_key.id],
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&api_key), None)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Retorna erro caso a chave não tenha a permissão na loja
+    pub fn check_permission(&self, shop: &Shop, permission: Permission) -> Result<()> {
+        if self.shop == shop.slug && self.scopes.iter().any(|scope| scope.allows(permission)) {
+            Ok(())
+        } else {
+            Err(Error::builder()
+                .code(Status::Forbidden)
+                .description("Essa chave de API não tem permissão para isso")
+                .build())
+        }
+    }
+}
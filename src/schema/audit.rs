@@ -0,0 +1,277 @@
+use crate::schema::{
+    ApiKey, Category, Member, Page, PageRequest, Product, Purchase, Settings, Shop, TwoFactor,
+    User, Variant,
+};
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Utc};
+use postgres::{Row, Transaction};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+/// Quem fez uma ação registrada: um usuário ou uma integração, por chave de API
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Actor {
+    pub user: Option<String>,
+    pub api_key: Option<Uuid>,
+}
+
+impl From<&User> for Actor {
+    fn from(user: &User) -> Actor {
+        Actor {
+            user: Some(user.email.clone()),
+            api_key: None,
+        }
+    }
+}
+
+impl From<&ApiKey> for Actor {
+    fn from(key: &ApiKey) -> Actor {
+        Actor {
+            user: None,
+            api_key: Some(key.id),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Refund,
+    Cancel,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Refund => "refund",
+            AuditAction::Cancel => "cancel",
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditAction {
+    type Error = Error;
+    fn try_from(action: &str) -> Result<Self> {
+        match action {
+            "create" => Ok(AuditAction::Create),
+            "update" => Ok(AuditAction::Update),
+            "delete" => Ok(AuditAction::Delete),
+            "refund" => Ok(AuditAction::Refund),
+            "cancel" => Ok(AuditAction::Cancel),
+            _ => Err(Error::builder()
+                .description(&format!("Ação desconhecida: '{}'", action))
+                .build()),
+        }
+    }
+}
+
+impl Serialize for AuditAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Entidades cujas alterações ficam registradas
+pub trait Audited: Serialize {
+    /// Tipo da entidade, como aparece no registro
+    const KIND: &'static str;
+    /// Identificador da entidade
+    fn audit_id(&self) -> String;
+}
+
+impl Audited for User {
+    const KIND: &'static str = "user";
+    fn audit_id(&self) -> String {
+        self.email.clone()
+    }
+}
+
+impl Audited for TwoFactor {
+    const KIND: &'static str = "two_factor";
+    fn audit_id(&self) -> String {
+        self.owner.clone()
+    }
+}
+
+impl Audited for Shop {
+    const KIND: &'static str = "shop";
+    fn audit_id(&self) -> String {
+        self.slug.clone()
+    }
+}
+
+impl Audited for Product {
+    const KIND: &'static str = "product";
+    fn audit_id(&self) -> String {
+        self.slug.clone()
+    }
+}
+
+impl Audited for Variant {
+    const KIND: &'static str = "variant";
+    fn audit_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl Audited for Category {
+    const KIND: &'static str = "category";
+    fn audit_id(&self) -> String {
+        self.slug.clone()
+    }
+}
+
+impl Audited for Purchase {
+    const KIND: &'static str = "purchase";
+    fn audit_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl Audited for Member {
+    const KIND: &'static str = "member";
+    fn audit_id(&self) -> String {
+        format!("{}/{}", self.shop, self.member)
+    }
+}
+
+impl Audited for ApiKey {
+    const KIND: &'static str = "api_key";
+    fn audit_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl Audited for Settings {
+    const KIND: &'static str = "settings";
+    fn audit_id(&self) -> String {
+        "settings".into()
+    }
+}
+
+/// Uma ação registrada. O registro é apenas de inserção: a database recusa alterações e remoções
+#[derive(PartialEq, Eq, Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub time: DateTime<Utc>,
+    /// Usuário que fez a ação, caso não tenha sido uma integração
+    pub actor: Option<String>,
+    /// Chave de API usada, caso tenha sido uma integração
+    pub api_key: Option<Uuid>,
+    pub action: AuditAction,
+    /// Tipo da entidade alterada
+    pub target_type: String,
+    /// Identificador da entidade alterada
+    pub target: String,
+    /// Estado antes da ação, caso a entidade já existisse
+    pub before: Option<Value>,
+    /// Estado depois da ação, caso a entidade ainda exista
+    pub after: Option<Value>,
+}
+
+impl TryFrom<Row> for AuditEntry {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let action: String = row.try_get("action")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            time: row.try_get("time")?,
+            actor: row.try_get("actor")?,
+            api_key: row.try_get("api_key")?,
+            action: action.as_str().try_into()?,
+            target_type: row.try_get("target_type")?,
+            target: row.try_get("target")?,
+            before: row.try_get("before")?,
+            after: row.try_get("after")?,
+        })
+    }
+}
+
+/// Filtros de uma consulta ao registro. Os omitidos não restringem nada
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub api_key: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub target_type: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditEntry {
+    /// Dentro da transação da alteração, registra uma ação sobre uma entidade, com o estado dela
+    /// antes e depois. Assim, a alteração nunca fica sem registro
+    pub fn record<T: Audited>(
+        tx: &mut Transaction,
+        actor: &Actor,
+        action: AuditAction,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) -> Result<()> {
+        let target = match after.or(before) {
+            Some(target) => target.audit_id(),
+            None => return Err(Error::builder().description("Nada a registrar").build()),
+        };
+        let before = before.map(serde_json::to_value).transpose()?;
+        let after = after.map(serde_json::to_value).transpose()?;
+        tx.execute(
+            "INSERT INTO audit_log
+            (time, actor, api_key, action, target_type, target, before, after)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &Utc::now(),
+                &actor.user,
+                &actor.api_key,
+                &action.as_str(),
+                &T::KIND,
+                &target,
+                &before,
+                &after,
+            ],
+        )?;
+        Ok(())
+    }
+    /// Lista uma página das entradas que passam pelos filtros, das mais recentes para as mais
+    /// antigas
+    pub async fn list(
+        db: &Database,
+        filter: AuditFilter,
+        page: PageRequest,
+    ) -> Result<Page<AuditEntry>> {
+        let action = filter.action.map(|action| action.as_str());
+        db.run(move |db| {
+            page.fetch(
+                db,
+                "*",
+                "FROM audit_log
+                WHERE ($1::citext IS NULL OR actor = $1)
+                AND ($2::uuid IS NULL OR api_key = $2)
+                AND ($3::text IS NULL OR action = $3)
+                AND ($4::text IS NULL OR target_type = $4)
+                AND ($5::text IS NULL OR target = $5)
+                AND ($6::timestamptz IS NULL OR time >= $6)
+                AND ($7::timestamptz IS NULL OR time < $7)",
+                "ORDER BY time DESC, id DESC",
+                &[
+                    &filter.actor,
+                    &filter.api_key,
+                    &action,
+                    &filter.target_type,
+                    &filter.target,
+                    &filter.since,
+                    &filter.until,
+                ],
+            )
+        })
+        .await
+    }
+}
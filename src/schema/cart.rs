@@ -0,0 +1,277 @@
+use crate::schema::{Actor, Order, OrderLine, Product, Shop, User, Variant};
+use crate::{Database, Error, Result};
+
+use postgres::Row;
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct CartItem {
+    pub product: String,
+    /// Variante escolhida, caso o produto tenha variantes
+    pub variant: Option<Uuid>,
+    pub amount: i32,
+    /// Preço unitário do produto (ou da variante) quando foi adicionado ao carrinho
+    pub price: Decimal,
+}
+
+impl TryFrom<Row> for CartItem {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            product: row.try_get("product")?,
+            variant: row.try_get("variant")?,
+            amount: row.try_get("amount")?,
+            price: row.try_get("price")?,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct Cart {
+    pub id: Uuid,
+    pub owner: String,
+    /// Loja à qual o carrinho se restringe, ou nenhuma para um carrinho geral
+    pub shop: Option<String>,
+    pub items: Vec<CartItem>,
+}
+
+impl Cart {
+    /// Lê o carrinho de um usuário (numa loja, ou o geral), criando caso ainda não exista
+    pub async fn read(db: &Database, owner: &User, shop: Option<&Shop>) -> Result<Cart> {
+        let owner = owner.email.clone();
+        let shop = shop.map(|s| s.slug.clone());
+        db.run(move |db| {
+            db.execute(
+                "INSERT INTO carts (owner, shop)
+                VALUES ($1, $2)
+                ON CONFLICT (owner, (COALESCE(shop, ''))) DO NOTHING",
+                &[&owner, &shop],
+            )?;
+            let cart = db.query_one(
+                "SELECT id
+                FROM carts
+                WHERE owner = $1 AND shop IS NOT DISTINCT FROM $2",
+                &[&owner, &shop],
+            )?;
+            let id: Uuid = cart.try_get("id")?;
+            let items = db
+                .query(
+                    "SELECT *
+                    FROM cart_items
+                    WHERE cart = $1
+                    ORDER BY product, variant",
+                    &[&id],
+                )?
+                .into_iter()
+                .map(CartItem::try_from)
+                .collect::<Result<_>>()?;
+            Ok(Cart {
+                id,
+                owner,
+                shop,
+                items,
+            })
+        })
+        .await
+    }
+    /// Valor total dos itens, pelos preços registrados no carrinho
+    pub fn total(&self) -> Decimal {
+        self.items
+            .iter()
+            .map(|item| item.price * Decimal::from(item.amount))
+            .sum()
+    }
+    /// Adiciona unidades de um produto (ou de uma variante dele) ao carrinho, somando com as que
+    /// já estavam lá. Produtos com variantes só são adicionados por elas
+    pub async fn add(
+        &self,
+        db: &Database,
+        product: &Product,
+        variant: Option<&Variant>,
+        amount: i32,
+    ) -> Result<()> {
+        self.check_product(product)?;
+        if amount <= 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A quantidade deve ser positiva")
+                .build());
+        }
+        let id = self.id;
+        let price = variant.map_or(product.price, |v| v.price(product));
+        let variant = variant.map(|v| v.id);
+        let product = product.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            if variant.is_none() && Variant::exists(&mut tx, &product.slug)? {
+                return Err(Error::builder()
+                    .code(Status::BadRequest)
+                    .description(&format!("Escolha uma variante de '{}'", product.slug))
+                    .build());
+            }
+            tx.execute(
+                "INSERT INTO cart_items (cart, product, variant, amount, price)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (cart, product, (COALESCE(variant, '00000000-0000-0000-0000-000000000000'::uuid)))
+                DO UPDATE
+                SET amount = cart_items.amount + excluded.amount,
+                price = excluded.price",
+                &[&id, &product.slug, &variant, &amount, &price],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Altera a quantidade de um item. Uma quantidade zero remove o item
+    pub async fn set_amount(
+        &self,
+        db: &Database,
+        product: &str,
+        variant: Option<Uuid>,
+        amount: i32,
+    ) -> Result<()> {
+        if amount < 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A quantidade não pode ser negativa")
+                .build());
+        }
+        if amount == 0 {
+            return self.remove(db, product, variant).await;
+        }
+        let id = self.id;
+        let product: String = product.into();
+        let updated = db
+            .run(move |db| {
+                db.execute(
+                    "UPDATE cart_items
+                    SET amount = $1
+                    WHERE cart = $2 AND product = $3 AND variant IS NOT DISTINCT FROM $4",
+                    &[&amount, &id, &product, &variant],
+                )
+            })
+            .await?;
+        if updated == 0 {
+            return Err(Error::builder()
+                .code(Status::NotFound)
+                .description("Esse produto não está no carrinho")
+                .build());
+        }
+        Ok(())
+    }
+    /// Remove um item do carrinho
+    pub async fn remove(&self, db: &Database, product: &str, variant: Option<Uuid>) -> Result<()> {
+        let id = self.id;
+        let product: String = product.into();
+        db.run(move |db| {
+            db.execute(
+                "DELETE FROM cart_items
+                WHERE cart = $1 AND product = $2 AND variant IS NOT DISTINCT FROM $3",
+                &[&id, &product, &variant],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+    /// Transforma todos os itens do carrinho num pedido, numa única transação. Os itens continuam
+    /// no carrinho até que o pedido seja pago (veja `clear`), para não se perderem caso o
+    /// pagamento seja recusado.
+    ///
+    /// Caso o preço de algum produto tenha mudado desde que foi adicionado, nada é comprado: o
+    /// carrinho é atualizado com os novos preços, para que o usuário os revise.
+    pub async fn checkout(&self, db: &Database, actor: impl Into<Actor>) -> Result<Order> {
+        if self.items.is_empty() {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("O carrinho está vazio")
+                .build());
+        }
+        let actor = actor.into();
+        let cart = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            let mut changed = Vec::new();
+            for item in &cart.items {
+                let product = Product::read_for_update(&mut tx, &item.product)?;
+                let price = match item.variant {
+                    Some(id) => Variant::read_for_update(&mut tx, &product, id)?.price(&product),
+                    None => product.price,
+                };
+                if price != item.price {
+                    changed.push((product.slug, item.variant, price));
+                }
+            }
+
+            if !changed.is_empty() {
+                tx.rollback()?;
+                for (product, variant, price) in &changed {
+                    db.execute(
+                        "UPDATE cart_items
+                        SET price = $1
+                        WHERE cart = $2 AND product = $3 AND variant IS NOT DISTINCT FROM $4",
+                        &[price, &cart.id, product, variant],
+                    )?;
+                }
+                return Err(Error::builder()
+                    .code(Status::Conflict)
+                    .description("O preço de alguns produtos mudou, revise o carrinho")
+                    .build());
+            }
+
+            let lines: Vec<_> = cart
+                .items
+                .iter()
+                .map(|item| OrderLine {
+                    product: item.product.clone(),
+                    variant: item.variant,
+                    amount: item.amount,
+                })
+                .collect();
+            let order = Order::insert(&mut tx, &cart.owner, &lines, &actor)?;
+            tx.commit()?;
+            Ok(order)
+        })
+        .await
+    }
+    /// Remove do carrinho os itens dessa leitura dele, já pedidos e pagos. Itens e unidades
+    /// adicionados depois continuam lá
+    pub async fn clear(&self, db: &Database) -> Result<()> {
+        let cart = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            for item in &cart.items {
+                tx.execute(
+                    "UPDATE cart_items
+                    SET amount = amount - $4
+                    WHERE cart = $1 AND product = $2 AND variant IS NOT DISTINCT FROM $3
+                    AND amount > $4",
+                    &[&cart.id, &item.product, &item.variant, &item.amount],
+                )?;
+                tx.execute(
+                    "DELETE FROM cart_items
+                    WHERE cart = $1 AND product = $2 AND variant IS NOT DISTINCT FROM $3
+                    AND amount <= $4",
+                    &[&cart.id, &item.product, &item.variant, &item.amount],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Verifica se o produto pode ir para esse carrinho
+    fn check_product(&self, product: &Product) -> Result<()> {
+        match &self.shop {
+            Some(shop) if shop != &product.shop => Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Esse produto não é da loja desse carrinho")
+                .build()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::schema::{Actor, AuditAction, AuditEntry, Product, Shop};
+use crate::{Database, Error, Result};
+
+use postgres::Row;
+use rocket::http::Status;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+
+/// Categoria de produtos, comum a todas as lojas. Categorias formam uma árvore: uma categoria sem
+/// `parent` é uma raiz
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Category {
+    pub slug: String,
+    pub name: String,
+    pub parent: Option<String>,
+}
+
+impl TryFrom<Row> for Category {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            slug: row.try_get("slug")?,
+            name: row.try_get("name")?,
+            parent: row.try_get("parent")?,
+        })
+    }
+}
+
+impl Category {
+    pub async fn read(db: &Database, slug: &str) -> Result<Category> {
+        let slug: String = slug.into();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM categories
+                WHERE slug = $1",
+                &[&slug],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Categoria não encontrada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Lista todas as categorias, em ordem alfabética
+    pub async fn list(db: &Database) -> Result<Vec<Category>> {
+        db.run(move |db| {
+            db.query(
+                "SELECT *
+                FROM categories
+                ORDER BY name, slug",
+                &[],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Category::try_from)
+        .collect()
+    }
+    /// Categorias acima dessa na árvore, da raiz até a categoria pai
+    pub async fn ancestors(&self, db: &Database) -> Result<Vec<Category>> {
+        let parent = self.parent.clone();
+        db.run(move |db| {
+            db.query(
+                "WITH RECURSIVE ancestors AS (
+                    SELECT *, 0 AS depth
+                    FROM categories
+                    WHERE slug = $1
+                    UNION
+                    SELECT categories.*, ancestors.depth + 1
+                    FROM categories
+                    INNER JOIN ancestors
+                    ON categories.slug = ancestors.parent
+                )
+                SELECT slug, name, parent
+                FROM ancestors
+                ORDER BY depth DESC",
+                &[&parent],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Category::try_from)
+        .collect()
+    }
+    pub async fn create(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let category = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "INSERT INTO categories (slug, name, parent)
+                VALUES ($1, $2, $3)",
+                &[&category.slug, &category.name, &category.parent],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Uma categoria com esse identificador já existe")
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&category))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Modifica informações, registrando a alteração feita pelo ator. A categoria não pode ficar
+    /// abaixo dela mesma na árvore
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Category,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
+        let category = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            let cycle: bool = tx
+                .query_one(
+                    "WITH RECURSIVE descendants AS (
+                        SELECT slug
+                        FROM categories
+                        WHERE slug = $1
+                        UNION
+                        SELECT categories.slug
+                        FROM categories
+                        INNER JOIN descendants
+                        ON categories.parent = descendants.slug
+                    )
+                    SELECT EXISTS (SELECT 1 FROM descendants WHERE slug = $2)",
+                    &[&before.slug, &category.parent],
+                )?
+                .try_get(0)?;
+            if cycle {
+                return Err(Error::builder()
+                    .code(Status::BadRequest)
+                    .description("Uma categoria não pode ficar dentro dela mesma")
+                    .build());
+            }
+            tx.execute(
+                "UPDATE categories SET slug = $1, name = $2, parent = $3
+                WHERE slug = $4",
+                &[
+                    &category.slug,
+                    &category.name,
+                    &category.parent,
+                    &before.slug,
+                ],
+            )
+            .map_err(|e| {
+                Error::builder_from(e).description("Não foi possível atualizar informações")
+            })?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&category),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Remove a categoria, que não pode ter subcategorias. Os produtos dela apenas deixam de
+    /// estar nela
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let category = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM categories
+                WHERE slug = $1",
+                &[&category.slug],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::Conflict)
+                    .description("Remova ou mova as subcategorias antes de remover a categoria")
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&category), None)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Categorias de um produto
+    pub async fn list_from_product(db: &Database, product: &Product) -> Result<Vec<Category>> {
+        let product = product.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT categories.*
+                FROM categories
+                INNER JOIN product_categories
+                ON product_categories.category = categories.slug
+                WHERE product_categories.product = $1
+                ORDER BY categories.name, categories.slug",
+                &[&product],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Category::try_from)
+        .collect()
+    }
+    /// Categorias com algum produto da loja
+    pub async fn list_from_shop(db: &Database, shop: &Shop) -> Result<Vec<Category>> {
+        let shop = shop.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT DISTINCT categories.*
+                FROM categories
+                INNER JOIN product_categories
+                ON product_categories.category = categories.slug
+                INNER JOIN products
+                ON products.slug = product_categories.product
+                WHERE products.shop = $1
+                ORDER BY categories.name, categories.slug",
+                &[&shop],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Category::try_from)
+        .collect()
+    }
+    /// Troca as categorias de um produto
+    pub async fn set_for_product(
+        db: &Database,
+        product: &Product,
+        categories: Vec<String>,
+    ) -> Result<()> {
+        let product = product.slug.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM product_categories
+                WHERE product = $1",
+                &[&product],
+            )?;
+            for category in categories {
+                tx.execute(
+                    "INSERT INTO product_categories (product, category)
+                    VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING",
+                    &[&product, &category],
+                )
+                .map_err(|e| {
+                    Error::builder_from(e)
+                        .code(Status::BadRequest)
+                        .description(&format!("A categoria '{}' não existe", category))
+                })?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,115 @@
+use crate::mail::{Mail, Mailer};
+use crate::schema::{Actor, AuditAction, AuditEntry, User};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::Status;
+use std::convert::TryInto;
+
+/// Um pedido de confirmação de email, com token de uso único. Na database só fica o hash do token
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct EmailVerification {
+    pub token: String,
+    pub owner: String,
+    pub expires: DateTime<Utc>,
+}
+
+impl EmailVerification {
+    /// Por quanto tempo um link de confirmação é válido
+    pub fn lifetime() -> Duration {
+        Duration::days(2)
+    }
+    /// Gera um novo token de confirmação para o usuário, invalidando os anteriores
+    pub async fn create(db: &Database, key: &TokenKey, user: &User) -> Result<EmailVerification> {
+        let verification = EmailVerification {
+            token: User::generate_token()?,
+            owner: user.email.clone(),
+            expires: Utc::now() + EmailVerification::lifetime(),
+        };
+        let new_verification = verification.clone();
+        let hash = key.hash(&verification.token);
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM email_verifications
+                WHERE owner = $1",
+                &[&new_verification.owner],
+            )?;
+            tx.execute(
+                "INSERT INTO email_verifications (token_hash, owner, expires)
+                VALUES ($1, $2, $3)",
+                &[&hash, &new_verification.owner, &new_verification.expires],
+            )?;
+            tx.commit()
+        })
+        .await?;
+        Ok(verification)
+    }
+    /// Gera um token e envia o link de confirmação para o email do usuário
+    pub async fn send(
+        db: &Database,
+        key: &TokenKey,
+        mailer: &dyn Mailer,
+        user: &User,
+    ) -> Result<()> {
+        let verification = EmailVerification::create(db, key, user).await?;
+        mailer
+            .send(Mail {
+                to: user.email.clone(),
+                subject: "Confirme seu email".into(),
+                body: format!(
+                    "Olá, {}!\n\n\
+                    Para confirmar que esse email é seu, acesse o link abaixo. Ele expira em {} \
+                    dias.\n\n\
+                    https://cincobola.misterio.me/users/verify?token={}",
+                    user.name,
+                    EmailVerification::lifetime().num_days(),
+                    verification.token
+                ),
+            })
+            .await
+    }
+    /// Consome um token (ainda válido), marcando o email do dono como confirmado e registrando a
+    /// confirmação. Retorna o usuário confirmado
+    pub async fn consume(db: &Database, key: &TokenKey, token: &str) -> Result<User> {
+        let hash = key.hash(token);
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            let user: User = tx
+                .query_one(
+                    "WITH verification AS (
+                        DELETE FROM email_verifications
+                        WHERE token_hash = $1 AND expires > now()
+                        RETURNING owner
+                    )
+                    UPDATE users
+                    SET verified = true
+                    FROM verification
+                    WHERE users.email = verification.owner
+                    RETURNING users.*",
+                    &[&hash],
+                )
+                .map_err(|e| {
+                    Error::builder_from(e)
+                        .code(Status::BadRequest)
+                        .description("Link de confirmação inválido ou expirado")
+                })?
+                .try_into()?;
+            let before = User {
+                verified: false,
+                ..user.clone()
+            };
+            AuditEntry::record(
+                &mut tx,
+                &Actor::from(&user),
+                AuditAction::Update,
+                Some(&before),
+                Some(&user),
+            )?;
+            tx.commit()?;
+            Ok(user)
+        })
+        .await
+    }
+}
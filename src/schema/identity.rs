@@ -0,0 +1,133 @@
+use crate::oidc::Identity;
+use crate::schema::User;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Utc};
+use postgres::Row;
+use rocket::http::Status;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+
+/// Vínculo entre um usuário e uma identidade num provedor externo (OpenID Connect)
+#[derive(PartialEq, Eq, Clone, Debug, Serialize)]
+pub struct UserIdentity {
+    pub issuer: String,
+    /// Identificador do usuário no provedor
+    pub subject: String,
+    pub owner: String,
+    pub created: DateTime<Utc>,
+}
+
+impl TryFrom<Row> for UserIdentity {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            issuer: row.try_get("issuer")?,
+            subject: row.try_get("subject")?,
+            owner: row.try_get("owner")?,
+            created: row.try_get("created")?,
+        })
+    }
+}
+
+impl UserIdentity {
+    pub async fn read(db: &Database, issuer: &str, subject: &str) -> Result<UserIdentity> {
+        let issuer: String = issuer.into();
+        let subject: String = subject.into();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM user_identities
+                WHERE issuer = $1 AND subject = $2",
+                &[&issuer, &subject],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Identidade não vinculada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    pub async fn create(&self, db: &Database) -> Result<()> {
+        let identity = self.clone();
+        db.run(move |db| {
+            db.execute(
+                "INSERT INTO user_identities (issuer, subject, owner, created)
+                VALUES ($1, $2, $3, $4)",
+                &[
+                    &identity.issuer,
+                    &identity.subject,
+                    &identity.owner,
+                    &identity.created,
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+    /// Encontra o usuário de uma identidade confirmada pelo provedor. Uma identidade ainda não
+    /// vinculada é vinculada ao usuário com o mesmo email, desde que tanto o provedor quanto o
+    /// usuário o tenham confirmado; caso ninguém use o email, um usuário novo é criado
+    pub async fn sign_in(db: &Database, identity: &Identity) -> Result<User> {
+        match UserIdentity::read(db, &identity.issuer, &identity.subject).await {
+            Ok(linked) => return User::read(db, &linked.owner).await,
+            Err(e) if e.code() == Status::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let email = match &identity.email {
+            Some(email) if identity.email_verified => email.clone(),
+            _ => {
+                return Err(Error::builder()
+                    .code(Status::Forbidden)
+                    .description("O provedor de identidade não confirmou o seu email")
+                    .build())
+            }
+        };
+        let user = match User::read(db, &email).await {
+            // Quem criou uma conta com o email e não o confirmou pode não ser o dono dele, e
+            // continuaria entrando pela senha e pelas sessões abertas
+            Ok(user) if !user.verified => {
+                return Err(Error::builder()
+                    .code(Status::Conflict)
+                    .description(
+                        "Já existe uma conta com esse email, ainda não confirmada. Confirme o \
+                        email pelo link enviado a ele antes de entrar pelo provedor",
+                    )
+                    .build())
+            }
+            Ok(user) => user,
+            Err(e) if e.code() == Status::NotFound => {
+                let user = User {
+                    name: identity
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| email.split('@').next().unwrap_or_default().into()),
+                    email,
+                    // Uma senha aleatória, que ninguém conhece. Caso queira entrar com senha,
+                    // o usuário pode redefini-la
+                    password: User::hash_password(&User::generate_token()?)?,
+                    token: None,
+                    admin: false,
+                    verified: true,
+                    cpf: None,
+                };
+                user.create(db).await?;
+                user
+            }
+            Err(e) => return Err(e),
+        };
+
+        UserIdentity {
+            issuer: identity.issuer.clone(),
+            subject: identity.subject.clone(),
+            owner: user.email.clone(),
+            created: Utc::now(),
+        }
+        .create(db)
+        .await?;
+        Ok(user)
+    }
+}
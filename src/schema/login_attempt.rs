@@ -0,0 +1,184 @@
+use crate::schema::{ClientInfo, TwoFactor, User};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Duration, Utc};
+use postgres::Row;
+use rocket::http::Status;
+use std::convert::TryFrom;
+
+/// Quantas falhas seguidas uma conta pode ter antes de começar a esperar entre tentativas
+const ACCOUNT_FREE_ATTEMPTS: i64 = 3;
+/// Quantas falhas um mesmo IP pode ter antes de começar a esperar. É maior que o da conta, já que
+/// várias pessoas podem compartilhar um IP
+const IP_FREE_ATTEMPTS: i64 = 20;
+/// A partir de quantas falhas além das livres a conta (ou IP) fica bloqueada
+const LOCKOUT_ATTEMPTS: i64 = 10;
+
+/// Falhas recentes de login de uma conta ou IP
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct Failures {
+    count: i64,
+    last: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<Row> for Failures {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            count: row.try_get("count")?,
+            last: row.try_get("last")?,
+        })
+    }
+}
+
+impl Failures {
+    /// Até quando é preciso esperar para tentar de novo, caso seja preciso: o tempo dobra a cada
+    /// falha, até virar um bloqueio
+    fn blocked_until(&self, free: i64) -> Option<DateTime<Utc>> {
+        let excess = self.count - free;
+        if excess < 0 {
+            return None;
+        }
+        let wait = if excess >= LOCKOUT_ATTEMPTS {
+            LoginAttempt::lockout()
+        } else {
+            Duration::seconds(1 << excess).min(LoginAttempt::lockout())
+        };
+        self.last.map(|last| last + wait)
+    }
+}
+
+/// Registro de tentativas de login, para limitar quem tenta adivinhar senhas
+pub struct LoginAttempt;
+
+impl LoginAttempt {
+    /// Por quanto tempo uma conta (ou IP) fica bloqueada após falhas demais
+    pub fn lockout() -> Duration {
+        Duration::minutes(15)
+    }
+    /// Janela em que as falhas são consideradas
+    pub fn window() -> Duration {
+        Duration::hours(1)
+    }
+    /// Verifica as credenciais, respeitando os limites de tentativas. Toda tentativa é
+    /// registrada, e a resposta é a mesma para email inexistente ou senha errada.
+    ///
+    /// Caso o usuário tenha autenticação em dois fatores, também é preciso um código do app
+    /// autenticador ou de recuperação. Um código errado conta como falha
+    pub async fn authenticate(
+        db: &Database,
+        key: &TokenKey,
+        email: &str,
+        password: &str,
+        code: Option<&str>,
+        client: &ClientInfo,
+    ) -> Result<User> {
+        LoginAttempt::check(db, email, client).await?;
+        let user = match User::read(db, email).await {
+            Ok(user) if user.verify_password(password) => Some(user),
+            Ok(_) => None,
+            Err(e) if e.code() == Status::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let user = match user {
+            Some(user) => user,
+            None => {
+                LoginAttempt::record(db, email, client, false).await?;
+                return Err(Error::builder()
+                    .code(Status::Unauthorized)
+                    .description("Email ou senha incorretos")
+                    .build());
+            }
+        };
+
+        if let Some(two_factor) = TwoFactor::read(db, &user).await? {
+            if two_factor.enabled {
+                // Sem código, a senha certa não conta como tentativa: o usuário só precisa
+                // completar o segundo passo
+                let code = code.filter(|code| !code.trim().is_empty()).ok_or_else(|| {
+                    Error::builder()
+                        .code(Status::Unauthorized)
+                        .description("Informe o código de autenticação em dois fatores")
+                        .build()
+                })?;
+                if !two_factor.verify(db, key, code).await? {
+                    LoginAttempt::record(db, email, client, false).await?;
+                    return Err(Error::builder()
+                        .code(Status::Unauthorized)
+                        .description("Código de autenticação inválido")
+                        .build());
+                }
+            }
+        }
+        LoginAttempt::record(db, email, client, true).await?;
+        Ok(user)
+    }
+    /// Retorna erro caso a conta ou o IP ainda precisem esperar antes de tentar de novo
+    async fn check(db: &Database, email: &str, client: &ClientInfo) -> Result<()> {
+        let email: String = email.into();
+        let ip = client.ip.clone();
+        let since = Utc::now() - LoginAttempt::window();
+        let (account, ip) = db
+            .run(move |db| -> Result<(Failures, Failures)> {
+                // Da conta, contam apenas as falhas desde o último sucesso
+                let account = db.query_one(
+                    "SELECT count(*), max(time) AS last
+                    FROM login_attempts
+                    WHERE email = $1 AND NOT success AND time > $2
+                    AND time > COALESCE(
+                        (SELECT max(time) FROM login_attempts WHERE email = $1 AND success),
+                        '-infinity'
+                    )",
+                    &[&email, &since],
+                )?;
+                // Do IP, contam todas, para que um atacante não zere a contagem entrando na
+                // própria conta
+                let ip = db.query_one(
+                    "SELECT count(*), max(time) AS last
+                    FROM login_attempts
+                    WHERE ip = $1 AND NOT success AND time > $2",
+                    &[&ip, &since],
+                )?;
+                Ok((Failures::try_from(account)?, Failures::try_from(ip)?))
+            })
+            .await?;
+
+        let until = account
+            .blocked_until(ACCOUNT_FREE_ATTEMPTS)
+            .max(ip.blocked_until(IP_FREE_ATTEMPTS));
+        match until {
+            Some(until) if until > Utc::now() => {
+                let seconds = (until - Utc::now()).num_seconds() + 1;
+                Err(Error::builder()
+                    .code(Status::TooManyRequests)
+                    .description(&format!(
+                        "Tentativas demais, tente novamente em {} segundos",
+                        seconds
+                    ))
+                    .build())
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Registra uma tentativa, aproveitando para limpar as antigas
+    async fn record(db: &Database, email: &str, client: &ClientInfo, success: bool) -> Result<()> {
+        let email: String = email.into();
+        let ip = client.ip.clone();
+        let since = Utc::now() - LoginAttempt::window();
+        db.run(move |db| -> Result<()> {
+            db.execute(
+                "DELETE FROM login_attempts
+                WHERE time <= $1",
+                &[&since],
+            )?;
+            db.execute(
+                "INSERT INTO login_attempts (email, ip, time, success)
+                VALUES ($1, $2, $3, $4)",
+                &[&email, &ip, &Utc::now(), &success],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,266 @@
+use crate::schema::{Actor, AuditAction, AuditEntry, Page, PageRequest, Shop, User};
+use crate::{Database, Error, Result};
+
+use postgres::Row;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+
+/// Papel de um membro da equipe de uma loja
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Controle total da loja, inclusive da equipe e das vendas
+    Owner,
+    /// Pode gerenciar os produtos
+    Editor,
+    /// Apenas acompanha a loja
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+    /// Se esse papel concede a permissão
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Editor => matches!(
+                permission,
+                Permission::ViewMembers | Permission::EditProducts
+            ),
+            Role::Viewer => matches!(permission, Permission::ViewMembers),
+        }
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = Error;
+    fn try_from(role: &str) -> Result<Self> {
+        match role {
+            "owner" => Ok(Role::Owner),
+            "editor" => Ok(Role::Editor),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(Error::builder()
+                .description(&format!("Papel desconhecido: '{}'", role))
+                .build()),
+        }
+    }
+}
+
+/// Ações restritas à equipe de uma loja
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Permission {
+    /// Alterar as informações da loja
+    EditShop,
+    /// Remover a loja
+    DeleteShop,
+    /// Ver a equipe
+    ViewMembers,
+    /// Adicionar, alterar e remover membros da equipe
+    ManageMembers,
+    /// Adicionar, alterar e remover produtos
+    EditProducts,
+    /// Criar e revogar as chaves de API da loja
+    ManageApiKeys,
+    /// Ver compras, pedidos e faturamento
+    ViewSales,
+    /// Reembolsar e cancelar compras
+    Refund,
+}
+
+impl Permission {
+    /// Mensagem de erro para quem não tem a permissão
+    pub fn denied(&self) -> Error {
+        let description = match self {
+            Permission::EditShop => "Você não tem permissão para modificar essa loja",
+            Permission::DeleteShop => "Você não tem permissão para remover essa loja",
+            Permission::ViewMembers => "Você não tem permissão para ver a equipe dessa loja",
+            Permission::ManageMembers => {
+                "Você não tem permissão para gerenciar a equipe dessa loja"
+            }
+            Permission::EditProducts => {
+                "Você não tem permissão para gerenciar os produtos dessa loja"
+            }
+            Permission::ManageApiKeys => {
+                "Você não tem permissão para gerenciar as chaves de API dessa loja"
+            }
+            Permission::ViewSales => "Você não tem permissão para ver as vendas dessa loja",
+            Permission::Refund => "Você não tem permissão para reembolsar compras dessa loja",
+        };
+        Error::builder()
+            .code(Status::Forbidden)
+            .description(description)
+            .build()
+    }
+}
+
+/// Um membro da equipe de uma loja. O gerente da loja é sempre dono, mesmo sem registro aqui
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct Member {
+    pub shop: String,
+    pub member: String,
+    pub role: Role,
+}
+
+impl TryFrom<Row> for Member {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let role: String = row.try_get("role")?;
+        Ok(Self {
+            shop: row.try_get("shop")?,
+            member: row.try_get("member")?,
+            role: role.as_str().try_into()?,
+        })
+    }
+}
+
+impl Member {
+    pub async fn read(db: &Database, shop: &Shop, email: &str) -> Result<Member> {
+        let shop = shop.slug.clone();
+        let email: String = email.into();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM shop_members
+                WHERE shop = $1 AND member = $2",
+                &[&shop, &email],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Membro não encontrado")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Lista uma página da equipe da loja: primeiro o gerente, que é sempre dono, e depois os
+    /// membros registrados
+    pub async fn list_from_shop(
+        db: &Database,
+        shop: &Shop,
+        page: PageRequest,
+    ) -> Result<Page<Member>> {
+        let shop = shop.slug.clone();
+        db.run(move |db| {
+            page.fetch(
+                db,
+                "shop, member, role",
+                "FROM (
+                    SELECT slug AS shop, manager AS member, 'owner' AS role, true AS manager
+                    FROM shops
+                    WHERE slug = $1
+                    UNION ALL
+                    SELECT shop, member, role, false AS manager
+                    FROM shop_members
+                    WHERE shop = $1
+                ) AS members",
+                "ORDER BY manager DESC, member",
+                &[&shop],
+            )
+        })
+        .await
+    }
+    pub async fn create(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let member = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "INSERT INTO shop_members (shop, member, role)
+                VALUES ($1, $2, $3)",
+                &[&member.shop, &member.member, &member.role.as_str()],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Esse usuário já faz parte da equipe")
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&member))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Troca o papel do membro, registrando a alteração feita pelo ator
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Member,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
+        let member = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE shop_members
+                SET role = $1
+                WHERE shop = $2 AND member = $3",
+                &[&member.role.as_str(), &member.shop, &member.member],
+            )?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&member),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let member = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM shop_members
+                WHERE shop = $1 AND member = $2",
+                &[&member.shop, &member.member],
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&member), None)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl Shop {
+    /// Papel do usuário na loja, caso faça parte da equipe
+    pub async fn role_of(&self, db: &Database, user: &User) -> Result<Option<Role>> {
+        if self.manager == user.email {
+            return Ok(Some(Role::Owner));
+        }
+        match Member::read(db, self, &user.email).await {
+            Ok(member) => Ok(Some(member.role)),
+            Err(e) if e.code() == Status::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Retorna erro caso o usuário não tenha a permissão nessa loja. Administradores podem tudo
+    pub async fn check_permission(
+        &self,
+        db: &Database,
+        user: &User,
+        permission: Permission,
+    ) -> Result<()> {
+        if user.admin {
+            return Ok(());
+        }
+        match self.role_of(db, user).await? {
+            Some(role) if role.allows(permission) => user.check_manager_two_factor(db).await,
+            _ => Err(permission.denied()),
+        }
+    }
+}
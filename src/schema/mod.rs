@@ -6,3 +6,35 @@ pub mod purchase;
 pub use purchase::*;
 pub mod product;
 pub use product::*;
+pub mod cart;
+pub use cart::*;
+pub mod order;
+pub use order::*;
+pub mod session;
+pub use session::*;
+pub mod password_reset;
+pub use password_reset::*;
+pub mod email_verification;
+pub use email_verification::*;
+pub mod member;
+pub use member::*;
+pub mod login_attempt;
+pub use login_attempt::*;
+pub mod api_key;
+pub use api_key::*;
+pub mod setting;
+pub use setting::*;
+pub mod two_factor;
+pub use two_factor::*;
+pub mod audit;
+pub use audit::*;
+pub mod identity;
+pub use identity::*;
+pub mod page;
+pub use page::*;
+pub mod category;
+pub use category::*;
+pub mod tag;
+pub use tag::*;
+pub mod variant;
+pub use variant::*;
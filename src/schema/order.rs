@@ -0,0 +1,387 @@
+use crate::payment::{self, PaymentProvider, PaymentStatus};
+use crate::schema::{Actor, AuditAction, AuditEntry, Page, PageRequest, Product, Purchase, User};
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Utc};
+use postgres::{Row, Transaction};
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl TryFrom<&str> for OrderStatus {
+    type Error = Error;
+    fn try_from(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(OrderStatus::Pending),
+            "paid" => Ok(OrderStatus::Paid),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            _ => Err(Error::builder()
+                .description(&format!("Status de pedido desconhecido: '{}'", status))
+                .build()),
+        }
+    }
+}
+
+/// Filtros da listagem de pedidos
+#[derive(Clone, Debug, Default)]
+pub struct OrderFilter {
+    pub purchaser: Option<String>,
+    /// Apenas pedidos com produtos da loja, que mostram só os itens dela (e o total deles)
+    pub shop: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct Order {
+    pub id: Uuid,
+    pub purchaser: Option<String>,
+    pub status: OrderStatus,
+    pub total: Decimal,
+    pub time: DateTime<Utc>,
+    pub items: Vec<Purchase>,
+}
+
+impl TryFrom<Row> for Order {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let status: String = row.try_get("status")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            purchaser: row.try_get("purchaser")?,
+            status: status.as_str().try_into()?,
+            total: row.try_get("total")?,
+            time: row.try_get("time")?,
+            items: Vec::new(),
+        })
+    }
+}
+
+/// Um item a ser pedido
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct OrderLine {
+    pub product: String,
+    /// Obrigatória para produtos com variantes
+    pub variant: Option<Uuid>,
+    pub amount: i32,
+}
+
+impl Order {
+    /// Dentro de uma transação, registra um pedido com uma compra para cada item, retirando as
+    /// unidades do estoque
+    pub fn insert(
+        tx: &mut Transaction,
+        purchaser: &str,
+        lines: &[OrderLine],
+        actor: &Actor,
+    ) -> Result<Order> {
+        if lines.is_empty() {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("O pedido deve ter pelo menos um item")
+                .build());
+        }
+        let mut order: Order = tx
+            .query_one(
+                "INSERT INTO orders (purchaser, status, total, time)
+                VALUES ($1, $2, 0, $3)
+                RETURNING *",
+                &[&purchaser, &OrderStatus::Pending.as_str(), &Utc::now()],
+            )?
+            .try_into()?;
+
+        for line in lines {
+            let (product, price) =
+                Product::sell_item(tx, &line.product, line.variant, line.amount)?;
+            let purchase = Purchase {
+                id: Uuid::new_v4(),
+                amount: line.amount,
+                paid: price,
+                time: Utc::now(),
+                product: Some(product.slug),
+                variant: line.variant,
+                purchaser: Some(purchaser.into()),
+                order: Some(order.id),
+                payment_status: PaymentStatus::Pending,
+                payment_reference: None,
+                refunded: 0,
+                refund: Decimal::ZERO,
+                refund_reason: None,
+            };
+            purchase.insert(tx)?;
+            AuditEntry::record(tx, actor, AuditAction::Create, None, Some(&purchase))?;
+            order.total += purchase.paid * Decimal::from(purchase.amount);
+            order.items.push(purchase);
+        }
+
+        tx.execute(
+            "UPDATE orders
+            SET total = $1
+            WHERE id = $2",
+            &[&order.total, &order.id],
+        )?;
+        Ok(order)
+    }
+    /// Registra um pedido numa única transação
+    pub async fn create(
+        db: &Database,
+        purchaser: &User,
+        lines: Vec<OrderLine>,
+        actor: impl Into<Actor>,
+    ) -> Result<Order> {
+        let actor = actor.into();
+        let purchaser = purchaser.email.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            let order = Order::insert(&mut tx, &purchaser, &lines, &actor)?;
+            tx.commit()?;
+            Ok(order)
+        })
+        .await
+    }
+    /// Marca o pedido (e suas compras) como pago
+    pub async fn set_paid(
+        &mut self,
+        db: &Database,
+        reference: &str,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = self.items.clone();
+        self.status = OrderStatus::Paid;
+        for item in &mut self.items {
+            item.payment_status = PaymentStatus::Captured;
+            item.payment_reference = Some(reference.into());
+        }
+        let order = self.clone();
+        let reference: String = reference.into();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE purchases
+                SET payment_status = $1,
+                payment_reference = $2
+                WHERE \"order\" = $3",
+                &[&PaymentStatus::Captured.as_str(), &reference, &order.id],
+            )?;
+            for (before, after) in before.iter().zip(&order.items) {
+                AuditEntry::record(
+                    &mut tx,
+                    &actor,
+                    AuditAction::Update,
+                    Some(before),
+                    Some(after),
+                )?;
+            }
+            tx.execute(
+                "UPDATE orders
+                SET status = $1
+                WHERE id = $2",
+                &[&OrderStatus::Paid.as_str(), &order.id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Cancela o pedido por falha no pagamento, devolvendo as unidades ao estoque
+    pub async fn fail_payment(&mut self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let before = self.items.clone();
+        self.status = OrderStatus::Cancelled;
+        for item in &mut self.items {
+            item.payment_status = PaymentStatus::Failed;
+        }
+        let order = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            for item in &order.items {
+                item.restock(&mut tx, item.amount)?;
+            }
+            tx.execute(
+                "UPDATE purchases
+                SET payment_status = $1
+                WHERE \"order\" = $2",
+                &[&PaymentStatus::Failed.as_str(), &order.id],
+            )?;
+            for (before, after) in before.iter().zip(&order.items) {
+                AuditEntry::record(
+                    &mut tx,
+                    &actor,
+                    AuditAction::Update,
+                    Some(before),
+                    Some(after),
+                )?;
+            }
+            tx.execute(
+                "UPDATE orders
+                SET status = $1
+                WHERE id = $2",
+                &[&order.status.as_str(), &order.id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Cobra o valor total do pedido. Caso o pagamento falhe, ele é cancelado e as unidades
+    /// voltam ao estoque
+    pub async fn pay(
+        &mut self,
+        db: &Database,
+        provider: &dyn PaymentProvider,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let payer = self.purchaser.clone().unwrap_or_default();
+        let reference = match payment::charge(provider, &payer, self.total).await {
+            Ok(reference) => reference,
+            Err(e) => {
+                self.fail_payment(db, actor).await?;
+                return Err(e);
+            }
+        };
+        if let Err(e) = self.set_paid(db, &reference, actor).await {
+            provider.refund(&reference, self.total).await.ok();
+            return Err(e);
+        }
+        Ok(())
+    }
+    pub async fn read(db: &Database, id: Uuid) -> Result<Order> {
+        Order::read_with_items(db, id, None).await
+    }
+    /// Lê um pedido com apenas os itens dessas lojas, e o total deles
+    pub async fn read_from_shops(db: &Database, id: Uuid, shops: Vec<String>) -> Result<Order> {
+        Order::read_with_items(db, id, Some(shops)).await
+    }
+    /// Lojas dos produtos do pedido
+    pub async fn shops(&self, db: &Database) -> Result<Vec<String>> {
+        let id = self.id;
+        db.run(move |db| {
+            db.query(
+                "SELECT DISTINCT products.shop
+                FROM purchases
+                INNER JOIN products
+                ON purchases.product = products.slug
+                WHERE purchases.\"order\" = $1
+                ORDER BY products.shop",
+                &[&id],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(|row| Ok(row.try_get("shop")?))
+        .collect()
+    }
+    /// Lê um pedido e os seus itens, opcionalmente apenas os de algumas lojas
+    async fn read_with_items(db: &Database, id: Uuid, shops: Option<Vec<String>>) -> Result<Order> {
+        let order: Order = db
+            .run(move |db| {
+                db.query_one(
+                    "SELECT *
+                    FROM orders
+                    WHERE id = $1",
+                    &[&id],
+                )
+                .map_err(|e| {
+                    Error::builder_from(e)
+                        .code(Status::NotFound)
+                        .description("Pedido não encontrado")
+                })
+            })
+            .await?
+            .try_into()?;
+        let mut orders = Order::with_items(db, vec![order], shops).await?;
+        Ok(orders.remove(0))
+    }
+    /// Lista uma página dos pedidos que passam pelos filtros, dos mais recentes para os mais
+    /// antigos
+    pub async fn list(
+        db: &Database,
+        filter: OrderFilter,
+        page: PageRequest,
+    ) -> Result<Page<Order>> {
+        let shops = filter.shop.clone().map(|shop| vec![shop]);
+        let mut orders: Page<Order> = db
+            .run(move |db| {
+                page.fetch(
+                    db,
+                    "*",
+                    "FROM orders
+                    WHERE ($1::citext IS NULL OR purchaser = $1)
+                    AND ($2::text IS NULL OR EXISTS (
+                        SELECT 1
+                        FROM purchases
+                        INNER JOIN products
+                        ON purchases.product = products.slug
+                        WHERE purchases.\"order\" = orders.id AND products.shop = $2
+                    ))",
+                    "ORDER BY time DESC, id DESC",
+                    &[&filter.purchaser, &filter.shop],
+                )
+            })
+            .await?;
+        orders.items = Order::with_items(db, orders.items, shops).await?;
+        Ok(orders)
+    }
+    /// Busca os itens de cada pedido, opcionalmente apenas os de algumas lojas (e nesse caso o
+    /// total passa a ser apenas o dos itens delas)
+    async fn with_items(
+        db: &Database,
+        mut orders: Vec<Order>,
+        shops: Option<Vec<String>>,
+    ) -> Result<Vec<Order>> {
+        let ids: Vec<Uuid> = orders.iter().map(|order| order.id).collect();
+        let partial = shops.is_some();
+        let purchases = db
+            .run(move |db| {
+                db.query(
+                    "SELECT purchases.*
+                    FROM purchases
+                    LEFT JOIN products
+                    ON purchases.product = products.slug
+                    WHERE purchases.\"order\" = ANY($1)
+                    AND ($2::text[] IS NULL OR products.shop = ANY($2))
+                    ORDER BY purchases.time",
+                    &[&ids, &shops],
+                )
+            })
+            .await?
+            .into_iter()
+            .map(Purchase::try_from)
+            .collect::<Result<Vec<Purchase>>>()?;
+
+        let mut items: HashMap<Uuid, Vec<Purchase>> = HashMap::new();
+        for purchase in purchases {
+            if let Some(order) = purchase.order {
+                items.entry(order).or_default().push(purchase);
+            }
+        }
+        for order in &mut orders {
+            order.items = items.remove(&order.id).unwrap_or_default();
+            if partial {
+                order.total = order.items.iter().map(Purchase::total).sum();
+            }
+        }
+        Ok(orders)
+    }
+}
@@ -0,0 +1,301 @@
+//! Paginação e ordenação das listagens
+use crate::{Error, Result};
+
+use postgres::types::ToSql;
+use postgres::{Client, Row};
+use rocket::http::Status;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+
+/// Itens por página, caso o cliente não escolha
+pub const DEFAULT_LIMIT: i64 = 50;
+/// Máximo de itens por página
+pub const MAX_LIMIT: i64 = 200;
+
+/// Qual página de uma listagem ler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest {
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+impl PageRequest {
+    /// Valida o tamanho e o deslocamento pedidos. O tamanho é limitado a `MAX_LIMIT`
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Result<PageRequest> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        let offset = offset.unwrap_or(0);
+        if limit <= 0 || offset < 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("O limite deve ser positivo e o deslocamento não pode ser negativo")
+                .build());
+        }
+        Ok(PageRequest {
+            limit: limit.min(MAX_LIMIT),
+            offset,
+        })
+    }
+    /// Lê a página de `SELECT {columns} {body} {order}`, junto com o total de linhas. Os
+    /// parâmetros de `body` vão de `$1` até `$n`; o limite e o deslocamento são adicionados depois
+    pub fn fetch<T: TryFrom<Row, Error = Error>>(
+        &self,
+        db: &mut Client,
+        columns: &str,
+        body: &str,
+        order: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Page<T>> {
+        let total: i64 = db
+            .query_one(format!("SELECT COUNT(*) {}", body).as_str(), params)?
+            .try_get(0)?;
+        let query = format!(
+            "SELECT {} {} {} LIMIT ${} OFFSET ${}",
+            columns,
+            body,
+            order,
+            params.len() + 1,
+            params.len() + 2
+        );
+        let mut paged = params.to_vec();
+        paged.push(&self.limit);
+        paged.push(&self.offset);
+        let items = db
+            .query(query.as_str(), &paged)?
+            .into_iter()
+            .map(T::try_from)
+            .collect::<Result<Vec<T>>>()?;
+        Ok(Page::new(items, total, self))
+    }
+}
+
+/// Uma página de uma listagem
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Quantos itens passam pelos filtros, em todas as páginas
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    /// Deslocamento da próxima página, caso exista
+    pub next_offset: Option<i64>,
+    /// Deslocamento da página anterior, caso exista
+    pub previous_offset: Option<i64>,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, request: &PageRequest) -> Page<T> {
+        let next = request.offset + request.limit;
+        Page {
+            items,
+            total,
+            limit: request.limit,
+            offset: request.offset,
+            next_offset: if next < total { Some(next) } else { None },
+            previous_offset: if request.offset > 0 {
+                Some((request.offset - request.limit).max(0))
+            } else {
+                None
+            },
+        }
+    }
+    /// Converte os itens da página, mantendo a paginação
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            limit: self.limit,
+            offset: self.offset,
+            next_offset: self.next_offset,
+            previous_offset: self.previous_offset,
+        }
+    }
+}
+
+/// Direção da ordenação
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Asc => "ASC",
+            Direction::Desc => "DESC",
+        }
+    }
+}
+
+impl TryFrom<&str> for Direction {
+    type Error = Error;
+    fn try_from(order: &str) -> Result<Self> {
+        match order {
+            "asc" => Ok(Direction::Asc),
+            "desc" => Ok(Direction::Desc),
+            _ => Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Ordem inválida, use 'asc' ou 'desc'")
+                .build()),
+        }
+    }
+}
+
+/// Colunas pelas quais uma listagem pode ser ordenada. Apenas elas chegam ao SQL
+pub trait SortColumn: Sized + Default {
+    /// Nomes aceitos, para a mensagem de erro
+    const NAMES: &'static str;
+    /// Direção usada caso o cliente não escolha
+    const DEFAULT_DIRECTION: Direction = Direction::Asc;
+    /// Nome da coluna no banco
+    fn column(&self) -> &'static str;
+    /// Lê o nome usado na query string
+    fn parse(name: &str) -> Option<Self>;
+}
+
+/// Ordenação de uma listagem
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sort<C> {
+    pub column: C,
+    pub order: Direction,
+}
+
+impl<C: SortColumn> Default for Sort<C> {
+    fn default() -> Self {
+        Sort {
+            column: C::default(),
+            order: C::DEFAULT_DIRECTION,
+        }
+    }
+}
+
+impl<C: SortColumn> Sort<C> {
+    pub fn new(column: Option<&str>, order: Option<&str>) -> Result<Sort<C>> {
+        let column = match column {
+            Some(name) => C::parse(name).ok_or_else(|| {
+                Error::builder()
+                    .code(Status::BadRequest)
+                    .description(&format!("Ordenação inválida, use {}", C::NAMES))
+                    .build()
+            })?,
+            None => C::default(),
+        };
+        let order = match order {
+            Some(order) => order.try_into()?,
+            None => C::DEFAULT_DIRECTION,
+        };
+        Ok(Sort { column, order })
+    }
+    /// Cláusula `ORDER BY`, desempatando pela chave primária para que as páginas sejam estáveis
+    pub fn order_by(&self, key: &str) -> String {
+        format!(
+            "ORDER BY {} {}, {} {}",
+            self.column.column(),
+            self.order.as_str(),
+            key,
+            self.order.as_str()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    enum TestSort {
+        #[default]
+        Name,
+        Price,
+    }
+
+    impl SortColumn for TestSort {
+        const NAMES: &'static str = "'name' ou 'price'";
+        fn column(&self) -> &'static str {
+            match self {
+                TestSort::Name => "name",
+                TestSort::Price => "price",
+            }
+        }
+        fn parse(name: &str) -> Option<Self> {
+            match name {
+                "name" => Some(TestSort::Name),
+                "price" => Some(TestSort::Price),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn sort_defaults() {
+        let sort = Sort::<TestSort>::new(None, None).unwrap();
+        assert_eq!(sort, Sort::default());
+        assert_eq!(sort.order_by("slug"), "ORDER BY name ASC, slug ASC");
+    }
+
+    #[test]
+    fn sort_by_whitelisted_column() {
+        let sort = Sort::<TestSort>::new(Some("price"), Some("desc")).unwrap();
+        assert_eq!(sort.column, TestSort::Price);
+        assert_eq!(sort.order_by("slug"), "ORDER BY price DESC, slug DESC");
+    }
+
+    #[test]
+    fn sort_rejects_other_columns() {
+        for name in &[
+            "slug",
+            "Price",
+            "price DESC",
+            "price; DROP TABLE products",
+            "",
+        ] {
+            let error = Sort::<TestSort>::new(Some(name), None).unwrap_err();
+            assert_eq!(error.code(), Status::BadRequest);
+            assert!(error.message().contains(TestSort::NAMES));
+        }
+    }
+
+    #[test]
+    fn sort_rejects_other_orders() {
+        for order in &["ASC", "up", "desc, 1", ""] {
+            let error = Sort::<TestSort>::new(Some("name"), Some(order)).unwrap_err();
+            assert_eq!(error.code(), Status::BadRequest);
+        }
+    }
+
+    #[test]
+    fn page_request_limits() {
+        assert_eq!(
+            PageRequest::new(None, None).unwrap(),
+            PageRequest::default()
+        );
+        assert_eq!(PageRequest::new(Some(1000), None).unwrap().limit, MAX_LIMIT);
+        for (limit, offset) in &[(Some(0), None), (Some(-1), None), (None, Some(-1))] {
+            let error = PageRequest::new(*limit, *offset).unwrap_err();
+            assert_eq!(error.code(), Status::BadRequest);
+        }
+    }
+
+    #[test]
+    fn page_offsets() {
+        let request = PageRequest::new(Some(10), Some(5)).unwrap();
+        let page = Page::new(vec![1, 2, 3], 20, &request);
+        assert_eq!(page.next_offset, Some(15));
+        assert_eq!(page.previous_offset, Some(0));
+
+        let request = PageRequest::new(Some(10), Some(10)).unwrap();
+        let page = Page::new(vec![1], 11, &request).map(|item| item * 2);
+        assert_eq!(page.items, vec![2]);
+        assert_eq!(page.next_offset, None);
+        assert_eq!(page.previous_offset, Some(0));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::schema::User;
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::Status;
+
+/// Um pedido de redefinição de senha, com token de uso único. Na database só fica o hash do token
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PasswordReset {
+    pub token: String,
+    pub owner: String,
+    pub expires: DateTime<Utc>,
+}
+
+impl PasswordReset {
+    /// Por quanto tempo um token de redefinição é válido
+    pub fn lifetime() -> Duration {
+        Duration::hours(1)
+    }
+    /// Gera um novo token de redefinição para o usuário, invalidando os anteriores
+    pub async fn create(db: &Database, key: &TokenKey, user: &User) -> Result<PasswordReset> {
+        let reset = PasswordReset {
+            token: User::generate_token()?,
+            owner: user.email.clone(),
+            expires: Utc::now() + PasswordReset::lifetime(),
+        };
+        let new_reset = reset.clone();
+        let hash = key.hash(&reset.token);
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM password_resets
+                WHERE owner = $1",
+                &[&new_reset.owner],
+            )?;
+            tx.execute(
+                "INSERT INTO password_resets (token_hash, owner, expires)
+                VALUES ($1, $2, $3)",
+                &[&hash, &new_reset.owner, &new_reset.expires],
+            )?;
+            tx.commit()
+        })
+        .await?;
+        Ok(reset)
+    }
+    /// Consome um token (ainda válido), retornando o email do dono. O token não pode ser usado
+    /// novamente
+    pub async fn consume(db: &Database, key: &TokenKey, token: &str) -> Result<String> {
+        let hash = key.hash(token);
+        let row = db
+            .run(move |db| {
+                db.query_one(
+                    "DELETE FROM password_resets
+                    WHERE token_hash = $1 AND expires > now()
+                    RETURNING owner",
+                    &[&hash],
+                )
+                .map_err(|e| {
+                    Error::builder_from(e)
+                        .code(Status::BadRequest)
+                        .description("Token de redefinição inválido ou expirado")
+                })
+            })
+            .await?;
+        Ok(row.try_get("owner")?)
+    }
+}
@@ -1,8 +1,8 @@
-use crate::schema::Shop;
+use crate::schema::{Actor, AuditAction, AuditEntry, Page, PageRequest, Shop, Sort, SortColumn};
 use crate::{Database, Error, Result};
 
-use postgres::Row;
-use rocket::http::Status;
+use postgres::{Row, Transaction};
+use rocket::http::{RawStr, Status};
 use rust_decimal::Decimal;
 use serde::Serialize;
 use std::convert::{TryFrom, TryInto};
@@ -17,6 +17,91 @@ pub struct Product {
     pub sold: i32,
     pub details: String,
     pub picture: String,
+    /// Miniatura de `picture`, caso a imagem tenha sido enviada para o armazenamento
+    pub thumbnail: Option<String>,
+    /// Eixos das variantes do produto, como tamanho e cor. Caso o produto tenha variantes, o
+    /// estoque e as vendas são a soma dos delas
+    pub options: Vec<String>,
+    /// Chaves, no armazenamento, da foto e da miniatura enviadas para o produto
+    #[serde(skip_serializing)]
+    pub uploads: Vec<String>,
+}
+
+/// Filtros da listagem de produtos
+#[derive(Clone, Debug, Default)]
+pub struct ProductFilter {
+    pub shop: Option<String>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    /// Apenas produtos com estoque
+    pub in_stock: bool,
+    /// Produtos na categoria ou em alguma subcategoria dela
+    pub category: Option<String>,
+    /// Produtos com essa tag
+    pub tag: Option<String>,
+}
+
+/// Colunas pelas quais os produtos podem ser ordenados
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProductSort {
+    #[default]
+    Slug,
+    Name,
+    Price,
+    Sold,
+}
+
+impl SortColumn for ProductSort {
+    const NAMES: &'static str = "'slug', 'name', 'price' ou 'sold'";
+    fn column(&self) -> &'static str {
+        match self {
+            ProductSort::Slug => "slug",
+            ProductSort::Name => "name",
+            ProductSort::Price => "price",
+            ProductSort::Sold => "sold",
+        }
+    }
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "slug" => Some(ProductSort::Slug),
+            "name" => Some(ProductSort::Name),
+            "price" => Some(ProductSort::Price),
+            "sold" => Some(ProductSort::Sold),
+            _ => None,
+        }
+    }
+}
+
+/// Produto encontrado numa busca
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub product: Product,
+    /// Relevância do produto para a busca, maior é melhor
+    pub rank: f32,
+    /// Trechos da descrição que casam com a busca, marcados com `<mark>`
+    pub snippet: String,
+}
+
+/// Marcam, no trecho vindo do banco (`chr(2)` e `chr(3)`), onde começa e termina cada termo
+/// encontrado. São removidos da descrição antes, e trocados por `<mark>` depois de escapar o
+/// HTML do trecho
+const HIGHLIGHT_START: &str = "\u{2}";
+const HIGHLIGHT_STOP: &str = "\u{3}";
+
+impl TryFrom<Row> for SearchResult {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let snippet: String = row.try_get("snippet")?;
+        Ok(Self {
+            rank: row.try_get("rank")?,
+            snippet: RawStr::new(&snippet)
+                .html_escape()
+                .replace(HIGHLIGHT_START, "<mark>")
+                .replace(HIGHLIGHT_STOP, "</mark>"),
+            product: row.try_into()?,
+        })
+    }
 }
 
 impl TryFrom<Row> for Product {
@@ -31,11 +116,58 @@ impl TryFrom<Row> for Product {
             sold: row.try_get("sold")?,
             details: row.try_get("details")?,
             picture: row.try_get("picture")?,
+            thumbnail: row.try_get("thumbnail")?,
+            options: row.try_get("options")?,
+            uploads: row.try_get("uploads")?,
         })
     }
 }
 
 impl Product {
+    /// Lê um produto dentro de uma transação, travando a linha até que ela termine
+    pub fn read_for_update(tx: &mut Transaction, slug: &str) -> Result<Product> {
+        tx.query_one(
+            "SELECT *
+            FROM products
+            WHERE slug = $1
+            FOR UPDATE",
+            &[&slug],
+        )
+        .map_err(|e| {
+            Error::builder_from(e)
+                .code(Status::NotFound)
+                .description("Produto não encontrado")
+        })?
+        .try_into()
+    }
+    /// Dentro de uma transação, retira unidades do estoque e as contabiliza como vendidas
+    pub fn sell(&mut self, tx: &mut Transaction, amount: i32) -> Result<()> {
+        if amount <= 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A quantidade comprada deve ser positiva")
+                .build());
+        }
+        if amount > self.available {
+            return Err(Error::builder()
+                .code(Status::Conflict)
+                .description(&format!(
+                    "Estoque insuficiente: há apenas {} unidade(s) de '{}'",
+                    self.available, self.slug
+                ))
+                .build());
+        }
+        tx.execute(
+            "UPDATE products
+            SET available = available - $1,
+            sold = sold + $1
+            WHERE slug = $2",
+            &[&amount, &self.slug],
+        )?;
+        self.available -= amount;
+        self.sold += amount;
+        Ok(())
+    }
     pub async fn read(db: &Database, slug: &str) -> Result<Product> {
         let slug: String = slug.into();
         db.run(move |db| {
@@ -54,19 +186,91 @@ impl Product {
         .await?
         .try_into()
     }
-    pub async fn list(db: &Database) -> Result<Vec<Product>> {
+    /// Lista uma página dos produtos que passam pelos filtros
+    pub async fn list(
+        db: &Database,
+        filter: ProductFilter,
+        sort: Sort<ProductSort>,
+        page: PageRequest,
+    ) -> Result<Page<Product>> {
         db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM products",
-                &[],
+            page.fetch(
+                db,
+                "*",
+                "FROM products
+                WHERE ($1::text IS NULL OR shop = $1)
+                AND ($2::numeric IS NULL OR price >= $2)
+                AND ($3::numeric IS NULL OR price <= $3)
+                AND (NOT $4 OR available > 0)
+                AND ($5::text IS NULL OR slug IN (
+                    SELECT product
+                    FROM product_categories
+                    WHERE category IN (
+                        WITH RECURSIVE tree AS (
+                            SELECT slug FROM categories WHERE slug = $5
+                            UNION
+                            SELECT categories.slug
+                            FROM categories
+                            INNER JOIN tree
+                            ON categories.parent = tree.slug
+                        )
+                        SELECT slug FROM tree
+                    )
+                ))
+                AND ($6::text IS NULL OR slug IN (
+                    SELECT product
+                    FROM product_tags
+                    WHERE tag = $6
+                ))",
+                &sort.order_by("slug"),
+                &[
+                    &filter.shop,
+                    &filter.min_price,
+                    &filter.max_price,
+                    &filter.in_stock,
+                    &filter.category,
+                    &filter.tag,
+                ],
             )
         })
-        .await?
-        .into_iter()
-        .map(Product::try_from)
-        .collect()
+        .await
     }
+    /// Busca produtos pelo nome e pela descrição, dos mais para os menos relevantes. A busca
+    /// ignora acentos e entende variações das palavras (como plurais), e aceita a sintaxe de
+    /// buscadores: `"frase exata"`, `or` e `-excluida`
+    pub async fn search(
+        db: &Database,
+        query: &str,
+        shop: Option<String>,
+        page: PageRequest,
+    ) -> Result<Page<SearchResult>> {
+        let query = query.trim().to_string();
+        if query.is_empty() {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Informe o que deseja buscar")
+                .build());
+        }
+        db.run(move |db| {
+            page.fetch(
+                db,
+                "products.*,
+                ts_rank(search, query) AS rank,
+                ts_headline(
+                    'public.portuguese_unaccent', translate(details, chr(2) || chr(3), ''), query,
+                    'StartSel=' || chr(2) || ', StopSel=' || chr(3)
+                    || ', MaxFragments=2, FragmentDelimiter=\" … \"'
+                ) AS snippet",
+                "FROM products, websearch_to_tsquery('public.portuguese_unaccent', $1) AS query
+                WHERE search @@ query
+                AND ($2::text IS NULL OR shop = $2)",
+                "ORDER BY rank DESC, slug ASC",
+                &[&query, &shop],
+            )
+        })
+        .await
+    }
+    /// Lista todos os produtos de uma loja
     pub async fn list_from_shop(db: &Database, shop: &Shop) -> Result<Vec<Product>> {
         let shop = shop.clone();
         db.run(move |db| {
@@ -82,23 +286,36 @@ impl Product {
         .map(Product::try_from)
         .collect()
     }
-    pub async fn delete(&self, db: &Database) -> Result<()> {
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
         let product = self.clone();
         db.run(move |db| {
-            db.execute(
+            let mut tx = db.transaction()?;
+            tx.execute(
                 "DELETE FROM products
                 WHERE slug = $1",
                 &[&product.slug],
-            )
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&product), None)?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
-    pub async fn update(&self, db: &Database, old_slug: &str) -> Result<()> {
+    /// Modifica informações, registrando a alteração feita pelo ator. Ao trocar de loja, o produto
+    /// perde as tags da anterior
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Product,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
         let product = self.clone();
-        let old_slug: String = old_slug.into();
         db.run(move |db| {
-            db.execute(
+            let mut tx = db.transaction()?;
+            tx.execute(
                 "UPDATE products
                 SET slug = $1,
                 shop = $2,
@@ -107,8 +324,11 @@ impl Product {
                 available = $5,
                 sold = $6,
                 details = $7,
-                picture = $8
-                WHERE slug = $9",
+                picture = $8,
+                thumbnail = $9,
+                options = $10,
+                uploads = $11
+                WHERE slug = $12",
                 &[
                     &product.slug,
                     &product.shop,
@@ -118,23 +338,44 @@ impl Product {
                     &product.sold,
                     &product.details,
                     &product.picture,
-                    &old_slug,
+                    &product.thumbnail,
+                    &product.options,
+                    &product.uploads,
+                    &before.slug,
                 ],
             )
             .map_err(|e| {
                 Error::builder_from(e).description("Não foi possível atualizar informações")
-            })
+            })?;
+            // As tags são da loja, e não acompanham o produto
+            if product.shop != before.shop {
+                tx.execute(
+                    "DELETE FROM product_tags
+                    WHERE product = $1",
+                    &[&product.slug],
+                )?;
+            }
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&product),
+            )?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
-    pub async fn create(&self, db: &Database) -> Result<()> {
+    pub async fn create(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
         let product = self.clone();
         db.run(move |db| {
-            db.execute(
+            let mut tx = db.transaction()?;
+            tx.execute(
                 "INSERT INTO products
-                (slug, shop, name, price, available, sold, details, picture)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                (slug, shop, name, price, available, sold, details, picture, thumbnail, options, uploads)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
                 &[
                     &product.slug,
                     &product.shop,
@@ -144,15 +385,20 @@ impl Product {
                     &product.sold,
                     &product.details,
                     &product.picture,
+                    &product.thumbnail,
+                    &product.options,
+                    &product.uploads,
                 ],
             )
             .map_err(|e| {
                 Error::builder_from(e)
                     .code(Status::BadRequest)
                     .description("O identificador especificado já está registrado")
-            })
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&product))?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
 }
@@ -1,43 +1,111 @@
-use crate::schema::{Product, Shop, User};
+use crate::payment::{self, PaymentProvider, PaymentStatus};
+use crate::schema::{
+    Actor, AuditAction, AuditEntry, Direction, Page, PageRequest, Product, Sort, SortColumn,
+};
 use crate::{Database, Error, Result};
 
 use chrono::{DateTime, Utc};
-use postgres::Row;
+use postgres::{Row, Transaction};
 use rocket::http::Status;
 use rust_decimal::Decimal;
 use serde::Serialize;
 use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
 
 #[derive(PartialEq, Eq, Debug, Clone, Serialize)]
 pub struct Purchase {
+    pub id: Uuid,
     pub amount: i32,
     pub paid: Decimal,
     pub time: DateTime<Utc>,
     pub product: Option<String>,
+    /// Variante comprada, caso o produto tenha variantes
+    pub variant: Option<Uuid>,
     pub purchaser: Option<String>,
+    /// Pedido do qual a compra faz parte
+    pub order: Option<Uuid>,
+    pub payment_status: PaymentStatus,
+    /// Referência do pagamento no provedor
+    pub payment_reference: Option<String>,
+    /// Quantas das unidades compradas foram devolvidas
+    pub refunded: i32,
+    /// Valor total reembolsado
+    pub refund: Decimal,
+    pub refund_reason: Option<String>,
+}
+
+/// Filtros da listagem de compras
+#[derive(Clone, Debug, Default)]
+pub struct PurchaseFilter {
+    pub purchaser: Option<String>,
+    pub product: Option<String>,
+    pub shop: Option<String>,
+    /// Horário mínimo
+    pub since: Option<DateTime<Utc>>,
+    /// Horário máximo (exclusivo)
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Colunas pelas quais as compras podem ser ordenadas. Por padrão, das mais recentes para as
+/// mais antigas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PurchaseSort {
+    #[default]
+    Time,
+    Paid,
+    Amount,
+}
+
+impl SortColumn for PurchaseSort {
+    const NAMES: &'static str = "'time', 'paid' ou 'amount'";
+    const DEFAULT_DIRECTION: Direction = Direction::Desc;
+    fn column(&self) -> &'static str {
+        match self {
+            PurchaseSort::Time => "time",
+            PurchaseSort::Paid => "paid",
+            PurchaseSort::Amount => "amount",
+        }
+    }
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "time" => Some(PurchaseSort::Time),
+            "paid" => Some(PurchaseSort::Paid),
+            "amount" => Some(PurchaseSort::Amount),
+            _ => None,
+        }
+    }
 }
 
 impl TryFrom<Row> for Purchase {
     type Error = Error;
     fn try_from(row: Row) -> Result<Self> {
+        let payment_status: String = row.try_get("payment_status")?;
         Ok(Self {
+            id: row.try_get("id")?,
             amount: row.try_get("amount")?,
             paid: row.try_get("paid")?,
             time: row.try_get("time")?,
             product: row.try_get("product")?,
+            variant: row.try_get("variant")?,
             purchaser: row.try_get("purchaser")?,
+            order: row.try_get("order")?,
+            payment_status: payment_status.as_str().try_into()?,
+            payment_reference: row.try_get("payment_reference")?,
+            refunded: row.try_get("refunded")?,
+            refund: row.try_get("refund")?,
+            refund_reason: row.try_get("refund_reason")?,
         })
     }
 }
 
 impl Purchase {
-    pub async fn read(db: &Database, time: DateTime<Utc>) -> Result<Purchase> {
+    pub async fn read(db: &Database, id: Uuid) -> Result<Purchase> {
         db.run(move |db| {
             db.query_one(
                 "SELECT *
                 FROM purchases
-                WHERE time = $1",
-                &[&time],
+                WHERE id = $1",
+                &[&id],
             )
             .map_err(|e| {
                 Error::builder_from(e)
@@ -48,79 +116,48 @@ impl Purchase {
         .await?
         .try_into()
     }
-    pub async fn list(db: &Database) -> Result<Vec<Purchase>> {
-        db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM purchases",
-                &[],
-            )
-        })
-        .await?
-        .into_iter()
-        .map(Purchase::try_from)
-        .collect()
-    }
-    pub async fn list_from_shop(db: &Database, shop: &Shop) -> Result<Vec<Purchase>> {
-        let shop = shop.clone();
+    /// Lista uma página das compras que passam pelos filtros
+    pub async fn list(
+        db: &Database,
+        filter: PurchaseFilter,
+        sort: Sort<PurchaseSort>,
+        page: PageRequest,
+    ) -> Result<Page<Purchase>> {
         db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM purchases
-                INNER JOIN products
-                ON purchases.product = products.slug
-                WHERE products.shop = $1",
-                &[&shop.slug],
-            )
-        })
-        .await?
-        .into_iter()
-        .map(Purchase::try_from)
-        .collect()
-    }
-    pub async fn list_from_product(db: &Database, product: &Product) -> Result<Vec<Purchase>> {
-        let product = product.clone();
-        db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM purchases
-                WHERE product = $1",
-                &[&product.slug],
-            )
-        })
-        .await?
-        .into_iter()
-        .map(Purchase::try_from)
-        .collect()
-    }
-    pub async fn list_from_user(db: &Database, user: &User) -> Result<Vec<Purchase>> {
-        let user = user.clone();
-        db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM purchases
-                WHERE purchaser = $1",
-                &[&user.email],
+            page.fetch(
+                db,
+                "*",
+                "FROM purchases
+                WHERE ($1::citext IS NULL OR purchaser = $1)
+                AND ($2::text IS NULL OR product = $2)
+                AND ($3::text IS NULL OR product IN (SELECT slug FROM products WHERE shop = $3))
+                AND ($4::timestamptz IS NULL OR time >= $4)
+                AND ($5::timestamptz IS NULL OR time < $5)",
+                &sort.order_by("id"),
+                &[
+                    &filter.purchaser,
+                    &filter.product,
+                    &filter.shop,
+                    &filter.since,
+                    &filter.until,
+                ],
             )
         })
-        .await?
-        .into_iter()
-        .map(Purchase::try_from)
-        .collect()
+        .await
     }
     pub async fn delete(&self, db: &Database) -> Result<()> {
         let purchase = self.clone();
         db.run(move |db| {
             db.execute(
                 "DELETE FROM purchases
-                WHERE time = $1",
-                &[&purchase.time],
+                WHERE id = $1",
+                &[&purchase.id],
             )
         })
         .await?;
         Ok(())
     }
-    pub async fn update(&self, db: &Database, old_time: DateTime<Utc>) -> Result<()> {
+    pub async fn update(&self, db: &Database) -> Result<()> {
         let purchase = self.clone();
         db.run(move |db| {
             db.execute(
@@ -130,14 +167,28 @@ impl Purchase {
                 time = $3,
                 product = $4,
                 purchaser = $5,
-                WHERE time = $6",
+                \"order\" = $6,
+                payment_status = $7,
+                payment_reference = $8,
+                refunded = $9,
+                refund = $10,
+                refund_reason = $11,
+                variant = $12
+                WHERE id = $13",
                 &[
                     &purchase.amount,
                     &purchase.paid,
                     &purchase.time,
                     &purchase.product,
                     &purchase.purchaser,
-                    &old_time,
+                    &purchase.order,
+                    &purchase.payment_status.as_str(),
+                    &purchase.payment_reference,
+                    &purchase.refunded,
+                    &purchase.refund,
+                    &purchase.refund_reason,
+                    &purchase.variant,
+                    &purchase.id,
                 ],
             )
             .map_err(|e| Error::builder_from(e).code(Status::BadRequest))
@@ -145,24 +196,288 @@ impl Purchase {
         .await?;
         Ok(())
     }
-    pub async fn create(&self, db: &Database) -> Result<()> {
+    /// Registra a compra dentro de uma transação, sem mexer no estoque
+    pub fn insert(&self, tx: &mut Transaction) -> Result<()> {
+        tx.execute(
+            "INSERT INTO purchases
+            (id, amount, paid, time, product, purchaser, \"order\", payment_status, payment_reference, variant)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            &[
+                &self.id,
+                &self.amount,
+                &self.paid,
+                &self.time,
+                &self.product,
+                &self.purchaser,
+                &self.order,
+                &self.payment_status.as_str(),
+                &self.payment_reference,
+                &self.variant,
+            ],
+        )
+        .map_err(|e| Error::builder_from(e).code(Status::BadRequest))?;
+        Ok(())
+    }
+    /// Registra a compra, retirando as unidades do estoque do produto (ou da variante) numa única
+    /// transação. O valor pago por unidade é o preço atual
+    pub async fn create(&mut self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let mut purchase = self.clone();
+        *self = db
+            .run(move |db| -> Result<Purchase> {
+                let mut tx = db.transaction()?;
+                let slug = purchase.product.as_deref().ok_or_else(|| {
+                    Error::builder()
+                        .code(Status::BadRequest)
+                        .description("A compra deve ser de algum produto")
+                        .build()
+                })?;
+                let (_, price) =
+                    Product::sell_item(&mut tx, slug, purchase.variant, purchase.amount)?;
+                purchase.paid = price;
+                purchase.insert(&mut tx)?;
+                AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&purchase))?;
+                tx.commit()?;
+                Ok(purchase)
+            })
+            .await?;
+        Ok(())
+    }
+    /// Dentro de uma transação, devolve unidades da compra ao estoque do produto e da variante
+    /// (caso ainda existam)
+    pub fn restock(&self, tx: &mut Transaction, amount: i32) -> Result<()> {
+        tx.execute(
+            "UPDATE products
+            SET available = available + $1,
+            sold = sold - $1
+            WHERE slug = $2",
+            &[&amount, &self.product],
+        )?;
+        tx.execute(
+            "UPDATE product_variants
+            SET available = available + $1,
+            sold = sold - $1
+            WHERE id = $2",
+            &[&amount, &self.variant],
+        )?;
+        Ok(())
+    }
+    /// Registra a situação do pagamento da compra
+    pub async fn set_payment(
+        &mut self,
+        db: &Database,
+        status: PaymentStatus,
+        reference: Option<String>,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = self.clone();
+        self.payment_status = status;
+        self.payment_reference = reference;
         let purchase = self.clone();
         db.run(move |db| {
-            db.execute(
-                "INSERT INTO purchases
-                (amount, paid, time, product, purchaser)
-                VALUES ($1, $2, $3, $4, $5)",
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE purchases
+                SET payment_status = $1,
+                payment_reference = $2
+                WHERE id = $3",
                 &[
-                    &purchase.amount,
-                    &purchase.paid,
-                    &purchase.time,
-                    &purchase.product,
-                    &purchase.purchaser,
+                    &purchase.payment_status.as_str(),
+                    &purchase.payment_reference,
+                    &purchase.id,
                 ],
-            )
-            .map_err(|e| Error::builder_from(e).code(Status::BadRequest))
+            )?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&purchase),
+            )?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
+        .await
+    }
+    /// Marca o pagamento como falho, devolvendo as unidades ao estoque
+    pub async fn fail_payment(&mut self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let before = self.clone();
+        self.payment_status = PaymentStatus::Failed;
+        let purchase = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            purchase.restock(&mut tx, purchase.amount)?;
+            tx.execute(
+                "UPDATE purchases
+                SET payment_status = $1
+                WHERE id = $2",
+                &[&purchase.payment_status.as_str(), &purchase.id],
+            )?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&purchase),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Valor total da compra
+    pub fn total(&self) -> Decimal {
+        self.paid * Decimal::from(self.amount)
+    }
+    /// Cobra a compra. Caso o pagamento falhe, ela é marcada como falha e as unidades voltam ao
+    /// estoque
+    pub async fn pay(
+        &mut self,
+        db: &Database,
+        provider: &dyn PaymentProvider,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let payer = self.purchaser.clone().unwrap_or_default();
+        let reference = match payment::charge(provider, &payer, self.total()).await {
+            Ok(reference) => reference,
+            Err(e) => {
+                self.fail_payment(db, actor).await?;
+                return Err(e);
+            }
+        };
+        if let Err(e) = self
+            .set_payment(db, PaymentStatus::Captured, Some(reference.clone()), actor)
+            .await
+        {
+            provider.refund(&reference, self.total()).await.ok();
+            return Err(e);
+        }
+        Ok(())
+    }
+    /// Dentro de uma transação, lê a compra travando a linha até que ela termine
+    fn read_for_update(tx: &mut Transaction, id: Uuid) -> Result<Purchase> {
+        tx.query_one(
+            "SELECT *
+            FROM purchases
+            WHERE id = $1
+            FOR UPDATE",
+            &[&id],
+        )
+        .map_err(|e| {
+            Error::builder_from(e)
+                .code(Status::NotFound)
+                .description("Compra não encontrada")
+        })?
+        .try_into()
+    }
+    /// Reserva unidades da compra para um reembolso, verificando a compra como está no banco
+    /// (e não como foi lida antes), para que reembolsos simultâneos não passem do total
+    async fn reserve_refund(&self, db: &Database, amount: i32) -> Result<Purchase> {
+        let id = self.id;
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            let mut purchase = Purchase::read_for_update(&mut tx, id)?;
+            if purchase.payment_status != PaymentStatus::Captured {
+                return Err(Error::builder()
+                    .code(Status::Conflict)
+                    .description(&format!(
+                        "Apenas compras pagas podem ser reembolsadas, essa está '{}'",
+                        purchase.payment_status.as_str()
+                    ))
+                    .build());
+            }
+            if amount <= 0 || amount > purchase.amount - purchase.refunded {
+                return Err(Error::builder()
+                    .code(Status::BadRequest)
+                    .description(&format!(
+                        "A quantidade a reembolsar deve ser entre 1 e {}",
+                        purchase.amount - purchase.refunded
+                    ))
+                    .build());
+            }
+            tx.execute(
+                "UPDATE purchases
+                SET refunded = refunded + $1
+                WHERE id = $2",
+                &[&amount, &id],
+            )?;
+            tx.commit()?;
+            purchase.refunded += amount;
+            Ok(purchase)
+        })
+        .await
+    }
+    /// Reembolsa algumas das unidades (ainda não reembolsadas) da compra, devolvendo-as ao
+    /// estoque. Caso todas tenham sido reembolsadas, a compra é considerada cancelada
+    pub async fn refund(
+        &mut self,
+        db: &Database,
+        provider: &dyn PaymentProvider,
+        amount: i32,
+        reason: &str,
+        action: AuditAction,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = self.clone();
+        // As unidades ficam reservadas enquanto o provedor faz o reembolso, e são liberadas caso
+        // ele falhe
+        let mut purchase = self.reserve_refund(db, amount).await?;
+        let value = purchase.paid * Decimal::from(amount);
+        if let Some(reference) = &purchase.payment_reference {
+            if let Err(e) = provider.refund(reference, value).await {
+                let id = purchase.id;
+                db.run(move |db| {
+                    db.execute(
+                        "UPDATE purchases
+                        SET refunded = refunded - $1
+                        WHERE id = $2",
+                        &[&amount, &id],
+                    )
+                })
+                .await?;
+                return Err(e);
+            }
+        }
+
+        let reason = reason.to_string();
+        *self = db
+            .run(move |db| -> Result<Purchase> {
+                let mut tx = db.transaction()?;
+                purchase.restock(&mut tx, amount)?;
+                let row = tx.query_one(
+                    "UPDATE purchases
+                    SET refund = refund + $1,
+                    refund_reason = $2,
+                    payment_status = CASE WHEN refunded = amount
+                        THEN 'refunded'
+                        ELSE payment_status
+                    END
+                    WHERE id = $3
+                    RETURNING *",
+                    &[&value, &reason, &purchase.id],
+                )?;
+                purchase = row.try_into()?;
+                // Um pedido em que todas as compras foram reembolsadas está cancelado
+                tx.execute(
+                    "UPDATE orders
+                    SET status = 'cancelled'
+                    WHERE id = $1
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM purchases
+                        WHERE \"order\" = $1 AND payment_status <> 'refunded'
+                    )",
+                    &[&purchase.order],
+                )?;
+                AuditEntry::record(&mut tx, &actor, action, Some(&before), Some(&purchase))?;
+                tx.commit()?;
+                Ok(purchase)
+            })
+            .await?;
         Ok(())
     }
 }
@@ -0,0 +1,200 @@
+use crate::schema::{Page, PageRequest, User, UserToken};
+use crate::token::TokenKey;
+use crate::{Database, Error, Result};
+
+use chrono::{DateTime, Duration, Utc};
+use postgres::Row;
+use rocket::http::Status;
+use rocket::request;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+/// Dados do cliente que abriu a sessão
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for ClientInfo {
+    type Error = Error;
+    async fn from_request(req: &'r request::Request<'_>) -> request::Outcome<Self, Error> {
+        request::Outcome::Success(ClientInfo {
+            user_agent: req.headers().get_one("User-Agent").map(String::from),
+            ip: req.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub owner: String,
+    /// Token da sessão, presente apenas logo após ser aberta. Na database só fica o hash
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+    pub created: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl TryFrom<Row> for Session {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            owner: row.try_get("owner")?,
+            token: None,
+            created: row.try_get("created")?,
+            last_seen: row.try_get("last_seen")?,
+            expires: row.try_get("expires")?,
+            user_agent: row.try_get("user_agent")?,
+            ip: row.try_get("ip")?,
+        })
+    }
+}
+
+impl Session {
+    /// Por quanto tempo uma sessão é válida
+    pub fn lifetime() -> Duration {
+        Duration::days(30)
+    }
+    /// Abre uma nova sessão para o usuário, com um token novo
+    pub async fn create(
+        db: &Database,
+        key: &TokenKey,
+        user: &User,
+        client: ClientInfo,
+    ) -> Result<Session> {
+        let now = Utc::now();
+        let token = User::generate_token()?;
+        let hash = key.hash(&token);
+        let session = Session {
+            id: Uuid::new_v4(),
+            owner: user.email.clone(),
+            token: Some(token),
+            created: now,
+            last_seen: now,
+            expires: now + Session::lifetime(),
+            user_agent: client.user_agent,
+            ip: client.ip,
+        };
+        let new_session = session.clone();
+        db.run(move |db| {
+            // Aproveitar para limpar as sessões expiradas do usuário
+            db.execute(
+                "DELETE FROM sessions
+                WHERE owner = $1 AND expires <= now()",
+                &[&new_session.owner],
+            )?;
+            db.execute(
+                "INSERT INTO sessions
+                (id, owner, token_hash, created, last_seen, expires, user_agent, ip)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &new_session.id,
+                    &new_session.owner,
+                    &hash,
+                    &new_session.created,
+                    &new_session.last_seen,
+                    &new_session.expires,
+                    &new_session.user_agent,
+                    &new_session.ip,
+                ],
+            )
+        })
+        .await?;
+        Ok(session)
+    }
+    pub async fn read(db: &Database, id: Uuid) -> Result<Session> {
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM sessions
+                WHERE id = $1 AND expires > now()",
+                &[&id],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Sessão não encontrada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Dado token, busca a sessão (ainda válida) correspondente
+    pub async fn read_from_token(
+        db: &Database,
+        key: &TokenKey,
+        token: &UserToken,
+    ) -> Result<Session> {
+        let token: String = token.clone().into();
+        let hash = key.hash(&token);
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM sessions
+                WHERE token_hash = $1 AND expires > now()",
+                &[&hash],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::Unauthorized)
+                    .description("Sessão inválida")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Lista uma página das sessões ainda válidas de um usuário, das usadas mais recentemente
+    /// para as mais antigas
+    pub async fn list_from_user(
+        db: &Database,
+        user: &User,
+        page: PageRequest,
+    ) -> Result<Page<Session>> {
+        let owner = user.email.clone();
+        db.run(move |db| {
+            page.fetch(
+                db,
+                "*",
+                "FROM sessions
+                WHERE owner = $1 AND expires > now()",
+                "ORDER BY last_seen DESC, id DESC",
+                &[&owner],
+            )
+        })
+        .await
+    }
+    /// Encerra (revoga) a sessão
+    pub async fn delete(&self, db: &Database) -> Result<()> {
+        let session = self.clone();
+        db.run(move |db| {
+            db.execute(
+                "DELETE FROM sessions
+                WHERE id = $1",
+                &[&session.id],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+    /// Encerra todas as sessões de um usuário
+    pub async fn delete_from_user(db: &Database, user: &User) -> Result<()> {
+        let user = user.clone();
+        db.run(move |db| {
+            db.execute(
+                "DELETE FROM sessions
+                WHERE owner = $1",
+                &[&user.email],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+}
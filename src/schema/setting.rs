@@ -0,0 +1,75 @@
+use crate::schema::{Actor, AuditAction, AuditEntry};
+use crate::{Database, Error, Result};
+
+use postgres::Row;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Configurações globais do site, alteráveis pelos administradores. As ausentes na database
+/// ficam com o valor padrão
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Exige que os membros da equipe das lojas usem autenticação em dois fatores
+    pub require_manager_2fa: bool,
+}
+
+impl Settings {
+    pub async fn read(db: &Database) -> Result<Settings> {
+        let rows = db
+            .run(move |db| {
+                db.query(
+                    "SELECT name, value
+                    FROM settings",
+                    &[],
+                )
+            })
+            .await?;
+        let settings = rows
+            .into_iter()
+            .map(|row: Row| Ok((row.try_get("name")?, row.try_get("value")?)))
+            .collect::<Result<Map<String, Value>>>()?;
+        Ok(serde_json::from_value(Value::Object(settings))?)
+    }
+    /// Substitui as configurações, registrando a alteração feita pelo ator
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Settings,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
+        let after = self.clone();
+        let settings = match serde_json::to_value(self)? {
+            Value::Object(settings) => settings,
+            _ => {
+                return Err(Error::builder()
+                    .description("Configurações em formato inválido")
+                    .build())
+            }
+        };
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            for (name, value) in &settings {
+                tx.execute(
+                    "INSERT INTO settings (name, value)
+                    VALUES ($1, $2)
+                    ON CONFLICT (name) DO UPDATE
+                    SET value = excluded.value",
+                    &[name, value],
+                )?;
+            }
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&after),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -1,4 +1,6 @@
-use crate::schema::User;
+use crate::schema::{
+    Actor, AuditAction, AuditEntry, Page, PageRequest, Product, Sort, SortColumn, User,
+};
 use crate::{Database, Error, Result};
 
 use postgres::Row;
@@ -13,8 +15,44 @@ pub struct Shop {
     pub color_dark: String,
     pub color_light: String,
     pub logo: String,
+    /// Miniatura do `logo`, caso a imagem tenha sido enviada para o armazenamento
+    pub logo_thumbnail: Option<String>,
     #[serde(skip_serializing)]
     pub manager: String,
+    /// Chaves, no armazenamento, do logo e da miniatura enviados para a loja
+    #[serde(skip_serializing)]
+    pub uploads: Vec<String>,
+}
+
+/// Filtros da listagem de lojas
+#[derive(Clone, Debug, Default)]
+pub struct ShopFilter {
+    pub manager: Option<String>,
+}
+
+/// Colunas pelas quais as lojas podem ser ordenadas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShopSort {
+    #[default]
+    Slug,
+    Name,
+}
+
+impl SortColumn for ShopSort {
+    const NAMES: &'static str = "'slug' ou 'name'";
+    fn column(&self) -> &'static str {
+        match self {
+            ShopSort::Slug => "slug",
+            ShopSort::Name => "name",
+        }
+    }
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "slug" => Some(ShopSort::Slug),
+            "name" => Some(ShopSort::Name),
+            _ => None,
+        }
+    }
 }
 
 impl TryFrom<Row> for Shop {
@@ -26,24 +64,32 @@ impl TryFrom<Row> for Shop {
             color_dark: row.try_get("color_dark")?,
             color_light: row.try_get("color_light")?,
             logo: row.try_get("logo")?,
+            logo_thumbnail: row.try_get("logo_thumbnail")?,
             manager: row.try_get("manager")?,
+            uploads: row.try_get("uploads")?,
         })
     }
 }
 
 impl Shop {
-    pub async fn list(db: &Database) -> Result<Vec<Shop>> {
+    /// Lista uma página das lojas que passam pelos filtros
+    pub async fn list(
+        db: &Database,
+        filter: ShopFilter,
+        sort: Sort<ShopSort>,
+        page: PageRequest,
+    ) -> Result<Page<Shop>> {
         db.run(move |db| {
-            db.query(
-                "SELECT *
-                FROM shops",
-                &[],
+            page.fetch(
+                db,
+                "*",
+                "FROM shops
+                WHERE ($1::citext IS NULL OR manager = $1)",
+                &sort.order_by("slug"),
+                &[&filter.manager],
             )
         })
-        .await?
-        .into_iter()
-        .map(Shop::try_from)
-        .collect()
+        .await
     }
     pub async fn read(db: &Database, slug: &str) -> Result<Shop> {
         let slug: String = slug.into();
@@ -78,56 +124,91 @@ impl Shop {
         .map(Shop::try_from)
         .collect()
     }
-    pub async fn delete(&self, db: &Database) -> Result<()> {
+    /// Apaga a loja e os produtos dela, registrando a remoção de cada um. Retorna os produtos
+    /// apagados
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<Vec<Product>> {
+        let actor = actor.into();
         let shop = self.clone();
         db.run(move |db| {
-            db.execute(
+            let mut tx = db.transaction()?;
+            let products = tx
+                .query(
+                    "DELETE FROM products
+                    WHERE shop = $1
+                    RETURNING *",
+                    &[&shop.slug],
+                )?
+                .into_iter()
+                .map(Product::try_from)
+                .collect::<Result<Vec<Product>>>()?;
+            tx.execute(
                 "DELETE FROM shops
                 WHERE slug = $1",
                 &[&shop.slug],
-            )
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&shop), None)?;
+            for product in &products {
+                AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(product), None)?;
+            }
+            tx.commit()?;
+            Ok(products)
         })
-        .await?;
-        Ok(())
+        .await
     }
-    pub async fn update(&self, db: &Database, old_slug: &str) -> Result<()> {
-        let old_slug: String = old_slug.into();
+    /// Modifica informações, registrando a alteração feita pelo ator
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Shop,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
         let shop = self.clone();
         db.run(move |db| {
-            db.execute(
-                "UPDATE shops SET slug = $1, name = $2, color_dark = $3, color_light = $4, logo = $5, manager = $6
-                WHERE slug = $6",
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE shops SET slug = $1, name = $2, color_dark = $3, color_light = $4, logo = $5, logo_thumbnail = $6, manager = $7, uploads = $8
+                WHERE slug = $9",
                 &[
                     &shop.slug,
                     &shop.name,
                     &shop.color_dark,
                     &shop.color_light,
                     &shop.logo,
+                    &shop.logo_thumbnail,
                     &shop.manager,
-                    &old_slug,
+                    &shop.uploads,
+                    &before.slug,
                 ],
             )
             .map_err(|e| {
                 Error::builder_from(e).description("Não foi possível atualizar informações")
-            })
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Update, Some(&before), Some(&shop))?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
-    pub async fn create(&self, db: &Database) -> Result<()> {
+    pub async fn create(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
         let shop = self.clone();
         db.run(move |db| {
-            db.execute(
-                "INSERT INTO shops (slug, name, color_dark, color_light, logo, manager) VALUES ($1, $2, $3, $4, $5, $6)",
-                &[&shop.slug, &shop.name, &shop.color_dark, &shop.color_light, &shop.logo, &shop.manager],
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "INSERT INTO shops (slug, name, color_dark, color_light, logo, logo_thumbnail, manager, uploads) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[&shop.slug, &shop.name, &shop.color_dark, &shop.color_light, &shop.logo, &shop.logo_thumbnail, &shop.manager, &shop.uploads],
             )
             .map_err(|e| {
                 Error::builder_from(e)
                     .code(Status::BadRequest)
                     .description("Uma loja com esse identificador já existe")
-            })
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&shop))?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
 }
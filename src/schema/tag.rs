@@ -0,0 +1,160 @@
+use crate::schema::{Product, Shop};
+use crate::{Database, Error, Result};
+
+use postgres::Row;
+use rocket::http::Status;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+
+/// Etiqueta livre que uma loja usa para organizar os seus produtos
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Tag {
+    pub shop: String,
+    pub slug: String,
+    pub name: String,
+}
+
+impl TryFrom<Row> for Tag {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            shop: row.try_get("shop")?,
+            slug: row.try_get("slug")?,
+            name: row.try_get("name")?,
+        })
+    }
+}
+
+impl Tag {
+    pub async fn read(db: &Database, shop: &Shop, slug: &str) -> Result<Tag> {
+        let shop = shop.slug.clone();
+        let slug: String = slug.into();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM tags
+                WHERE shop = $1 AND slug = $2",
+                &[&shop, &slug],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Tag não encontrada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    pub async fn list_from_shop(db: &Database, shop: &Shop) -> Result<Vec<Tag>> {
+        let shop = shop.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT *
+                FROM tags
+                WHERE shop = $1
+                ORDER BY name, slug",
+                &[&shop],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Tag::try_from)
+        .collect()
+    }
+    pub async fn create(&self, db: &Database) -> Result<()> {
+        let tag = self.clone();
+        db.run(move |db| {
+            db.execute(
+                "INSERT INTO tags (shop, slug, name)
+                VALUES ($1, $2, $3)",
+                &[&tag.shop, &tag.slug, &tag.name],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Uma tag com esse identificador já existe nessa loja")
+            })
+        })
+        .await?;
+        Ok(())
+    }
+    pub async fn update(&self, db: &Database, old_slug: &str) -> Result<()> {
+        let old_slug: String = old_slug.into();
+        let tag = self.clone();
+        db.run(move |db| {
+            db.execute(
+                "UPDATE tags SET slug = $1, name = $2
+                WHERE shop = $3 AND slug = $4",
+                &[&tag.slug, &tag.name, &tag.shop, &old_slug],
+            )
+            .map_err(|e| {
+                Error::builder_from(e).description("Não foi possível atualizar informações")
+            })
+        })
+        .await?;
+        Ok(())
+    }
+    pub async fn delete(&self, db: &Database) -> Result<()> {
+        let tag = self.clone();
+        db.run(move |db| {
+            db.execute(
+                "DELETE FROM tags
+                WHERE shop = $1 AND slug = $2",
+                &[&tag.shop, &tag.slug],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+    /// Tags de um produto
+    pub async fn list_from_product(db: &Database, product: &Product) -> Result<Vec<Tag>> {
+        let product = product.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT tags.*
+                FROM tags
+                INNER JOIN product_tags
+                ON product_tags.shop = tags.shop AND product_tags.tag = tags.slug
+                WHERE product_tags.product = $1
+                ORDER BY tags.name, tags.slug",
+                &[&product],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Tag::try_from)
+        .collect()
+    }
+    /// Troca as tags de um produto, que devem ser da loja dele
+    pub async fn set_for_product(
+        db: &Database,
+        product: &Product,
+        tags: Vec<String>,
+    ) -> Result<()> {
+        let product = product.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM product_tags
+                WHERE product = $1",
+                &[&product.slug],
+            )?;
+            for tag in tags {
+                tx.execute(
+                    "INSERT INTO product_tags (product, shop, tag)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING",
+                    &[&product.slug, &product.shop, &tag],
+                )
+                .map_err(|e| {
+                    Error::builder_from(e)
+                        .code(Status::BadRequest)
+                        .description(&format!("A tag '{}' não existe nessa loja", tag))
+                })?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
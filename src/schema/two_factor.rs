@@ -0,0 +1,315 @@
+use crate::schema::{Actor, AuditAction, AuditEntry, Settings, User};
+use crate::token::TokenKey;
+use crate::totp;
+use crate::{Database, Error, Result};
+
+use chrono::Utc;
+use postgres::{Row, Transaction};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rocket::http::Status;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto};
+
+/// Quantos códigos de recuperação são gerados de cada vez
+const RECOVERY_CODES: usize = 10;
+
+/// Autenticação em dois fatores (TOTP) de um usuário
+#[derive(PartialEq, Eq, Clone, Debug, Serialize)]
+pub struct TwoFactor {
+    pub owner: String,
+    /// Segredo compartilhado com o app autenticador
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Se já foi confirmada. Antes disso, o segredo é apenas uma inscrição em andamento
+    pub enabled: bool,
+    /// Último intervalo cujo código foi aceito, para que um código não seja usado duas vezes
+    #[serde(skip_serializing)]
+    pub last_step: Option<i64>,
+}
+
+impl TryFrom<Row> for TwoFactor {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        Ok(Self {
+            owner: row.try_get("owner")?,
+            secret: row.try_get("secret")?,
+            enabled: row.try_get("enabled")?,
+            last_step: row.try_get("last_step")?,
+        })
+    }
+}
+
+/// Gera um código de recuperação, no formato `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let code: String = thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(10)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Gera um novo conjunto de códigos de recuperação, junto com os hashes que são guardados
+fn generate_recovery_codes(key: &TokenKey) -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes = codes.iter().map(|code| key.hash(code)).collect();
+    (codes, hashes)
+}
+
+/// Dentro de uma transação, troca os códigos de recuperação do usuário pelos desses hashes
+fn replace_recovery_codes(tx: &mut Transaction, owner: &str, hashes: &[String]) -> Result<()> {
+    tx.execute(
+        "DELETE FROM recovery_codes
+        WHERE owner = $1",
+        &[&owner],
+    )?;
+    for hash in hashes {
+        tx.execute(
+            "INSERT INTO recovery_codes (owner, code_hash)
+            VALUES ($1, $2)",
+            &[&owner, hash],
+        )?;
+    }
+    Ok(())
+}
+
+/// Normaliza um código de recuperação digitado pelo usuário, antes de calcular o hash
+fn normalize_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if code.len() == 10 {
+        format!("{}-{}", &code[..5], &code[5..])
+    } else {
+        code
+    }
+}
+
+impl TwoFactor {
+    /// Lê a autenticação em dois fatores do usuário, caso ele tenha uma (ou esteja se inscrevendo)
+    pub async fn read(db: &Database, user: &User) -> Result<Option<TwoFactor>> {
+        let owner = user.email.clone();
+        db.run(move |db| {
+            db.query_opt(
+                "SELECT *
+                FROM two_factor
+                WHERE owner = $1",
+                &[&owner],
+            )
+        })
+        .await?
+        .map(TwoFactor::try_from)
+        .transpose()
+    }
+    /// Se o usuário tem a autenticação em dois fatores ativada
+    pub async fn is_enabled(db: &Database, user: &User) -> Result<bool> {
+        Ok(TwoFactor::read(db, user)
+            .await?
+            .is_some_and(|two_factor| two_factor.enabled))
+    }
+    /// Começa a inscrição, gerando um novo segredo. Ela só vale depois de confirmada
+    pub async fn enroll(db: &Database, user: &User) -> Result<TwoFactor> {
+        if TwoFactor::is_enabled(db, user).await? {
+            return Err(Error::builder()
+                .code(Status::Conflict)
+                .description("A autenticação em dois fatores já está ativada")
+                .build());
+        }
+        let two_factor = TwoFactor {
+            owner: user.email.clone(),
+            secret: totp::generate_secret(),
+            enabled: false,
+            last_step: None,
+        };
+        let new = two_factor.clone();
+        let actor = Actor::from(user);
+        db.run(move |db| -> Result<()> {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "INSERT INTO two_factor (owner, secret, enabled)
+                VALUES ($1, $2, false)
+                ON CONFLICT (owner) DO UPDATE
+                SET secret = excluded.secret, enabled = false, last_step = NULL",
+                &[&new.owner, &new.secret],
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&new))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        Ok(two_factor)
+    }
+    /// URI para cadastrar o segredo num app autenticador
+    pub fn uri(&self) -> String {
+        totp::uri(&self.secret, &self.owner)
+    }
+    /// Confirma a inscrição com um código do app, ativando a autenticação em dois fatores.
+    /// Retorna os códigos de recuperação, que só podem ser vistos agora
+    pub async fn confirm(
+        &mut self,
+        db: &Database,
+        key: &TokenKey,
+        code: &str,
+    ) -> Result<Vec<String>> {
+        if self.enabled {
+            return Err(Error::builder()
+                .code(Status::Conflict)
+                .description("A autenticação em dois fatores já está ativada")
+                .build());
+        }
+        if !self.verify_totp(db, code).await? {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("Código de autenticação inválido")
+                .build());
+        }
+        let (codes, hashes) = generate_recovery_codes(key);
+        let before = self.clone();
+        let after = TwoFactor {
+            enabled: true,
+            ..self.clone()
+        };
+        let two_factor = after.clone();
+        db.run(move |db| -> Result<()> {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE two_factor
+                SET enabled = true
+                WHERE owner = $1",
+                &[&two_factor.owner],
+            )?;
+            replace_recovery_codes(&mut tx, &two_factor.owner, &hashes)?;
+            let actor = Actor {
+                user: Some(two_factor.owner.clone()),
+                api_key: None,
+            };
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&two_factor),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        *self = after;
+        Ok(codes)
+    }
+    /// Gera novos códigos de recuperação, invalidando os anteriores
+    pub async fn regenerate_recovery_codes(
+        &self,
+        db: &Database,
+        key: &TokenKey,
+    ) -> Result<Vec<String>> {
+        let (codes, hashes) = generate_recovery_codes(key);
+        let two_factor = self.clone();
+        db.run(move |db| -> Result<()> {
+            let mut tx = db.transaction()?;
+            replace_recovery_codes(&mut tx, &two_factor.owner, &hashes)?;
+            let actor = Actor {
+                user: Some(two_factor.owner.clone()),
+                api_key: None,
+            };
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&two_factor),
+                Some(&two_factor),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        Ok(codes)
+    }
+    /// Desativa a autenticação em dois fatores, apagando o segredo e os códigos de recuperação
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let two_factor = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM recovery_codes
+                WHERE owner = $1",
+                &[&two_factor.owner],
+            )?;
+            tx.execute(
+                "DELETE FROM two_factor
+                WHERE owner = $1",
+                &[&two_factor.owner],
+            )?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Delete,
+                Some(&two_factor),
+                None,
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Verifica um código do app autenticador ou, caso não seja um, um código de recuperação
+    /// (que é consumido)
+    pub async fn verify(&self, db: &Database, key: &TokenKey, code: &str) -> Result<bool> {
+        if self.verify_totp(db, code).await? {
+            return Ok(true);
+        }
+        let owner = self.owner.clone();
+        let hash = key.hash(&normalize_recovery_code(code));
+        let consumed = db
+            .run(move |db| {
+                db.execute(
+                    "DELETE FROM recovery_codes
+                    WHERE owner = $1 AND code_hash = $2",
+                    &[&owner, &hash],
+                )
+            })
+            .await?;
+        Ok(consumed > 0)
+    }
+    /// Verifica um código do app. Cada intervalo só é aceito uma vez, e nunca um anterior ao
+    /// último aceito
+    async fn verify_totp(&self, db: &Database, code: &str) -> Result<bool> {
+        let now = Utc::now().timestamp().try_into().unwrap_or_default();
+        let step: i64 = match totp::verify(&self.secret, code, now) {
+            Some(step) => step.try_into().unwrap_or_default(),
+            None => return Ok(false),
+        };
+        let owner = self.owner.clone();
+        let accepted = db
+            .run(move |db| {
+                db.execute(
+                    "UPDATE two_factor
+                    SET last_step = $2
+                    WHERE owner = $1 AND (last_step IS NULL OR last_step < $2)",
+                    &[&owner, &step],
+                )
+            })
+            .await?;
+        Ok(accepted > 0)
+    }
+}
+
+impl User {
+    /// Retorna erro caso o site exija autenticação em dois fatores da equipe das lojas e o
+    /// usuário não a tenha ativado
+    pub async fn check_manager_two_factor(&self, db: &Database) -> Result<()> {
+        if !Settings::read(db).await?.require_manager_2fa || TwoFactor::is_enabled(db, self).await?
+        {
+            return Ok(());
+        }
+        Err(Error::builder()
+            .code(Status::Forbidden)
+            .description("Ative a autenticação em dois fatores para gerenciar lojas")
+            .build())
+    }
+}
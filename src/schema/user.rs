@@ -1,7 +1,10 @@
+use crate::csrf::CsrfToken;
+use crate::schema::{Actor, AuditAction, AuditEntry, Page, PageRequest, Sort, SortColumn};
+use crate::token::TokenKey;
 use crate::{Database, Error, Result};
 use postgres::Row;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use rocket::http::Status;
+use rocket::http::{Cookie, Method, SameSite, Status};
 use rocket::request;
 use serde::Serialize;
 use std::convert::{TryFrom, TryInto};
@@ -11,26 +14,58 @@ pub struct UserToken {
     token: String,
 }
 
+/// Nome do cookie privado que guarda o token de sessão do navegador
+pub const SESSION_COOKIE: &str = "session";
+
 impl From<UserToken> for String {
     fn from(f: UserToken) -> String {
         f.token
     }
 }
 
+impl From<String> for UserToken {
+    fn from(token: String) -> UserToken {
+        UserToken { token }
+    }
+}
+
+impl UserToken {
+    /// Cria o cookie que guarda esse token no navegador
+    pub fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, self.token.clone())
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .finish()
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> request::FromRequest<'r> for UserToken {
     type Error = Error;
     async fn from_request(req: &'r request::Request<'_>) -> request::Outcome<Self, Error> {
-        let token = req.headers().get("Authentication").next();
-        match token {
-            None => request::Outcome::Failure((
-                Status::Unauthorized,
-                Error::builder().missing_header("Authentication").build(),
-            )),
-            Some(token) => request::Outcome::Success(UserToken {
+        // Clientes da API mandam o token pelo header
+        if let Some(token) = req.headers().get("Authentication").next() {
+            return request::Outcome::Success(UserToken {
                 token: token.into(),
-            }),
+            });
+        }
+        // Navegadores mandam pelo cookie. Nesse caso, requests que alteram estado devem provar
+        // que partiram das nossas páginas
+        if let Some(cookie) = req.cookies().get_private(SESSION_COOKIE) {
+            if !matches!(req.method(), Method::Get | Method::Head | Method::Options) {
+                if let Err(e) = CsrfToken::verify_header(req) {
+                    return request::Outcome::Failure((e.code(), e));
+                }
+            }
+            return request::Outcome::Success(UserToken {
+                token: cookie.value().into(),
+            });
         }
+        request::Outcome::Failure((
+            Status::Unauthorized,
+            Error::builder().missing_header("Authentication").build(),
+        ))
     }
 }
 
@@ -39,11 +74,49 @@ pub struct User {
     pub email: String,
     pub name: String,
     pub admin: bool,
+    /// Se o usuário já confirmou que o email é dele
+    pub verified: bool,
+    /// CPF (ou CNPJ), apenas os dígitos
+    pub cpf: Option<String>,
     #[serde(skip_serializing)]
     pub password: String,
+    /// Token da sessão recém aberta, presente apenas no login
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<String>,
 }
 
+/// Filtros da listagem de usuários
+#[derive(Clone, Debug, Default)]
+pub struct UserFilter {
+    pub admin: Option<bool>,
+    pub verified: Option<bool>,
+}
+
+/// Colunas pelas quais os usuários podem ser ordenados
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UserSort {
+    #[default]
+    Email,
+    Name,
+}
+
+impl SortColumn for UserSort {
+    const NAMES: &'static str = "'email' ou 'name'";
+    fn column(&self) -> &'static str {
+        match self {
+            UserSort::Email => "email",
+            UserSort::Name => "name",
+        }
+    }
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "email" => Some(UserSort::Email),
+            "name" => Some(UserSort::Name),
+            _ => None,
+        }
+    }
+}
+
 impl TryFrom<Row> for User {
     type Error = Error;
     fn try_from(row: Row) -> Result<Self> {
@@ -51,8 +124,10 @@ impl TryFrom<Row> for User {
             email: row.try_get("email")?,
             name: row.try_get("name")?,
             admin: row.try_get("admin")?,
+            verified: row.try_get("verified")?,
+            cpf: row.try_get("cpf")?,
             password: row.try_get("password")?,
-            token: row.try_get("token")?,
+            token: None,
         })
     }
 }
@@ -63,7 +138,7 @@ impl User {
         let email: String = email.into();
         db.run(move |db| {
             db.query_one(
-                "SELECT email, name, password, admin, token
+                "SELECT email, name, password, admin, verified, cpf
                 FROM users
                 WHERE email = $1",
                 &[&email],
@@ -77,15 +152,22 @@ impl User {
         .await?
         .try_into()
     }
-    /// Dado token, busca um usuário na db
-    pub async fn read_from_token(db: &Database, token: &UserToken) -> Result<User> {
-        let token = token.clone();
+    /// Dado token, busca o dono da sessão (ainda válida) na db
+    pub async fn read_from_token(db: &Database, key: &TokenKey, token: &UserToken) -> Result<User> {
+        let hash = key.hash(&token.token);
         db.run(move |db| {
             db.query_one(
-                "SELECT email, name, password, admin, token
-                    FROM users
-                    WHERE token = $1",
-                &[&token.token],
+                "WITH session AS (
+                    UPDATE sessions
+                    SET last_seen = now()
+                    WHERE token_hash = $1 AND expires > now()
+                    RETURNING owner
+                )
+                SELECT email, name, password, admin, verified, cpf
+                FROM users
+                INNER JOIN session
+                ON session.owner = users.email",
+                &[&hash],
             )
             .map_err(|e| {
                 Error::builder_from(e)
@@ -96,69 +178,91 @@ impl User {
         .await?
         .try_into()
     }
-    /// Lista todos os usuários
-    pub async fn list(db: &Database) -> Result<Vec<User>> {
+    /// Lista uma página dos usuários que passam pelos filtros
+    pub async fn list(
+        db: &Database,
+        filter: UserFilter,
+        sort: Sort<UserSort>,
+        page: PageRequest,
+    ) -> Result<Page<User>> {
         db.run(move |db| {
-            db.query(
-                "SELECT email, name, password, admin, token
-                FROM users",
-                &[],
+            page.fetch(
+                db,
+                "email, name, password, admin, verified, cpf",
+                "FROM users
+                WHERE ($1::bool IS NULL OR admin = $1)
+                AND ($2::bool IS NULL OR verified = $2)",
+                &sort.order_by("email"),
+                &[&filter.admin, &filter.verified],
             )
         })
-        .await?
-        .into_iter()
-        .map(User::try_from)
-        .collect()
+        .await
     }
-    /// Modifica informações
-    pub async fn update(&self, db: &Database, old_email: &str) -> Result<()> {
-        let old_email: String = old_email.into();
+    /// Modifica informações, registrando a alteração feita pelo ator
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &User,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
         let user = self.clone();
         db.run(move |db| {
-            db.execute(
-                "UPDATE users SET email = $1, password = $2, name = $3, admin = $4, token = $5
-                WHERE email = $6",
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "UPDATE users SET email = $1, password = $2, name = $3, admin = $4, verified = $5, cpf = $6
+                WHERE email = $7",
                 &[
                     &user.email,
                     &user.password,
                     &user.name,
                     &user.admin,
-                    &user.token,
-                    &old_email,
+                    &user.verified,
+                    &user.cpf,
+                    &before.email,
                 ],
             )
             .map_err(|e| {
                 Error::builder_from(e).description("Não foi possível atualizar informações")
-            })
+            })?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Update, Some(&before), Some(&user))?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
-    /// Remove o usuário
-    pub async fn delete(&self, db: &Database) -> Result<()> {
+    /// Remove o usuário, registrando quem o removeu
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
         let user = self.clone();
         db.run(move |db| {
-            db.execute(
+            let mut tx = db.transaction()?;
+            tx.execute(
                 "DELETE FROM users
                 WHERE email = $1",
                 &[&user.email],
-            )
+            )?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&user), None)?;
+            tx.commit()?;
+            Ok(())
         })
-        .await?;
-        Ok(())
+        .await
     }
-    /// Utilizando os dados, registra um novo usuário
+    /// Utilizando os dados, registra um novo usuário. O registro é feito pelo próprio usuário
     pub async fn create(&self, db: &Database) -> Result<()> {
         let user = self.clone();
         db.run(move |db| {
-            db.execute(
-                "INSERT INTO users (email, password, name, admin, token) VALUES ($1, $2, $3, $4, $5)",
+            let mut tx = db.transaction()?;
+            tx.execute(
+                "INSERT INTO users (email, password, name, admin, verified, cpf) VALUES ($1, $2, $3, $4, $5, $6)",
                 &[
                     &user.email,
                     &user.password,
                     &user.name,
                     &user.admin,
-                    &user.token,
+                    &user.verified,
+                    &user.cpf,
                 ],
             )
             .map_err(|e| {
@@ -166,9 +270,41 @@ impl User {
                 Error::builder_from(e)
                     .code(Status::BadRequest)
                     .description("O email especificado já está registrado")
-            })
-        }).await?;
-        Ok(())
+            })?;
+            AuditEntry::record(&mut tx, &Actor::from(&user), AuditAction::Create, None, Some(&user))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Retorna erro caso o usuário ainda não tenha confirmado o email
+    pub fn check_verified(&self) -> Result<()> {
+        if self.verified {
+            Ok(())
+        } else {
+            Err(Error::builder()
+                .code(Status::Forbidden)
+                .description("Confirme seu email antes de continuar")
+                .build())
+        }
+    }
+    /// Valida um CPF (ou CNPJ), com ou sem pontuação, retornando apenas os dígitos. Um campo
+    /// vazio é aceito como ausente
+    pub fn parse_cpf(cpf: &str) -> Result<Option<String>> {
+        let digits: String = cpf
+            .chars()
+            .filter(|c| !matches!(c, '.' | '-' | '/') && !c.is_whitespace())
+            .collect();
+        if digits.is_empty() {
+            return Ok(None);
+        }
+        if !digits.chars().all(|c| c.is_ascii_digit()) || ![11, 14].contains(&digits.len()) {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("CPF ou CNPJ inválido")
+                .build());
+        }
+        Ok(Some(digits))
     }
     /// Dado uma senha em cleartext, verifica se ela bate com o hash armazenado
     pub fn verify_password(&self, password: &str) -> bool {
@@ -0,0 +1,307 @@
+use crate::schema::{Actor, AuditAction, AuditEntry, Product};
+use crate::{Database, Error, Result};
+
+use postgres::types::Json;
+use postgres::{Row, Transaction};
+use rocket::http::Status;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use uuid::Uuid;
+
+/// Uma das versões de um produto (como um tamanho e uma cor de uma camiseta), com estoque próprio
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct Variant {
+    pub id: Uuid,
+    pub product: String,
+    pub sku: String,
+    /// Valor de cada eixo de opções do produto, como `{"tamanho": "M", "cor": "azul"}`
+    pub options: BTreeMap<String, String>,
+    /// Preço próprio da variante. Caso ausente, vale o do produto
+    pub price: Option<Decimal>,
+    pub available: i32,
+    pub sold: i32,
+}
+
+impl TryFrom<Row> for Variant {
+    type Error = Error;
+    fn try_from(row: Row) -> Result<Self> {
+        let options: Json<BTreeMap<String, String>> = row.try_get("options")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            product: row.try_get("product")?,
+            sku: row.try_get("sku")?,
+            options: options.0,
+            price: row.try_get("price")?,
+            available: row.try_get("available")?,
+            sold: row.try_get("sold")?,
+        })
+    }
+}
+
+/// Dentro de uma transação, mantém o estoque e as vendas do produto iguais à soma dos das suas
+/// variantes
+fn sync_stock(tx: &mut Transaction, product: &str) -> Result<()> {
+    tx.execute(
+        "UPDATE products
+        SET available = variants.available,
+        sold = variants.sold
+        FROM (
+            SELECT SUM(available)::integer AS available, SUM(sold)::integer AS sold
+            FROM product_variants
+            WHERE product = $1
+        ) AS variants
+        WHERE slug = $1 AND variants.available IS NOT NULL",
+        &[&product],
+    )?;
+    Ok(())
+}
+
+impl Variant {
+    /// Preço de uma unidade dessa variante do produto
+    pub fn price(&self, product: &Product) -> Decimal {
+        self.price.unwrap_or(product.price)
+    }
+    /// Retorna erro caso as opções não sejam exatamente os eixos do produto
+    pub fn check_options(&self, product: &Product) -> Result<()> {
+        let mut axes = product.options.clone();
+        axes.sort();
+        axes.dedup();
+        let keys: Vec<&String> = self.options.keys().collect();
+        if keys != axes.iter().collect::<Vec<_>>() || self.options.values().any(String::is_empty) {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description(&format!(
+                    "A variante deve ter um valor para cada opção do produto: {}",
+                    product.options.join(", ")
+                ))
+                .build());
+        }
+        Ok(())
+    }
+    pub async fn read(db: &Database, product: &Product, id: Uuid) -> Result<Variant> {
+        let product = product.slug.clone();
+        db.run(move |db| {
+            db.query_one(
+                "SELECT *
+                FROM product_variants
+                WHERE product = $1 AND id = $2",
+                &[&product, &id],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::NotFound)
+                    .description("Variante não encontrada")
+            })
+        })
+        .await?
+        .try_into()
+    }
+    /// Lê uma variante dentro de uma transação, travando a linha até que ela termine
+    pub fn read_for_update(tx: &mut Transaction, product: &Product, id: Uuid) -> Result<Variant> {
+        tx.query_one(
+            "SELECT *
+            FROM product_variants
+            WHERE product = $1 AND id = $2
+            FOR UPDATE",
+            &[&product.slug, &id],
+        )
+        .map_err(|e| {
+            Error::builder_from(e)
+                .code(Status::NotFound)
+                .description(&format!("Variante não encontrada em '{}'", product.slug))
+        })?
+        .try_into()
+    }
+    pub async fn list_from_product(db: &Database, product: &Product) -> Result<Vec<Variant>> {
+        let product = product.slug.clone();
+        db.run(move |db| {
+            db.query(
+                "SELECT *
+                FROM product_variants
+                WHERE product = $1
+                ORDER BY sku",
+                &[&product],
+            )
+        })
+        .await?
+        .into_iter()
+        .map(Variant::try_from)
+        .collect()
+    }
+    /// Se o produto tem variantes, e por isso só pode ser vendido por elas
+    pub fn exists(tx: &mut Transaction, product: &str) -> Result<bool> {
+        Ok(tx
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM product_variants WHERE product = $1)",
+                &[&product],
+            )?
+            .try_get(0)?)
+    }
+    pub async fn create(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let variant = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            // Trava o produto antes das variantes, na mesma ordem das vendas
+            Product::read_for_update(&mut tx, &variant.product)?;
+            tx.execute(
+                "INSERT INTO product_variants (id, product, sku, options, price, available, sold)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &variant.id,
+                    &variant.product,
+                    &variant.sku,
+                    &Json(&variant.options),
+                    &variant.price,
+                    &variant.available,
+                    &variant.sold,
+                ],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Já existe uma variante com esse SKU ou com essas opções")
+            })?;
+            sync_stock(&mut tx, &variant.product)?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Create, None, Some(&variant))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Modifica a variante, registrando a alteração feita pelo ator
+    pub async fn update(
+        &self,
+        db: &Database,
+        before: &Variant,
+        actor: impl Into<Actor>,
+    ) -> Result<()> {
+        let actor = actor.into();
+        let before = before.clone();
+        let variant = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            // Trava o produto antes das variantes, na mesma ordem das vendas
+            Product::read_for_update(&mut tx, &variant.product)?;
+            tx.execute(
+                "UPDATE product_variants
+                SET sku = $1,
+                options = $2,
+                price = $3,
+                available = $4,
+                sold = $5
+                WHERE id = $6",
+                &[
+                    &variant.sku,
+                    &Json(&variant.options),
+                    &variant.price,
+                    &variant.available,
+                    &variant.sold,
+                    &variant.id,
+                ],
+            )
+            .map_err(|e| {
+                Error::builder_from(e)
+                    .code(Status::BadRequest)
+                    .description("Já existe uma variante com esse SKU ou com essas opções")
+            })?;
+            sync_stock(&mut tx, &variant.product)?;
+            AuditEntry::record(
+                &mut tx,
+                &actor,
+                AuditAction::Update,
+                Some(&before),
+                Some(&variant),
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Remove a variante. As compras dela continuam registradas, sem a referência
+    pub async fn delete(&self, db: &Database, actor: impl Into<Actor>) -> Result<()> {
+        let actor = actor.into();
+        let variant = self.clone();
+        db.run(move |db| {
+            let mut tx = db.transaction()?;
+            // Trava o produto antes das variantes, na mesma ordem das vendas
+            Product::read_for_update(&mut tx, &variant.product)?;
+            tx.execute(
+                "DELETE FROM product_variants
+                WHERE id = $1",
+                &[&variant.id],
+            )?;
+            sync_stock(&mut tx, &variant.product)?;
+            AuditEntry::record(&mut tx, &actor, AuditAction::Delete, Some(&variant), None)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    /// Dentro de uma transação, retira unidades do estoque da variante e as contabiliza como
+    /// vendidas. O produto deve ser atualizado junto, com `Product::sell`
+    pub fn sell(&mut self, tx: &mut Transaction, amount: i32) -> Result<()> {
+        if amount <= 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A quantidade comprada deve ser positiva")
+                .build());
+        }
+        if amount > self.available {
+            return Err(Error::builder()
+                .code(Status::Conflict)
+                .description(&format!(
+                    "Estoque insuficiente: há apenas {} unidade(s) de '{}'",
+                    self.available, self.sku
+                ))
+                .build());
+        }
+        tx.execute(
+            "UPDATE product_variants
+            SET available = available - $1,
+            sold = sold + $1
+            WHERE id = $2",
+            &[&amount, &self.id],
+        )?;
+        self.available -= amount;
+        self.sold += amount;
+        Ok(())
+    }
+}
+
+impl Product {
+    /// Dentro de uma transação, vende unidades do produto ou de uma variante dele, retornando o
+    /// produto atualizado e o preço unitário. Produtos com variantes só são vendidos por elas
+    pub fn sell_item(
+        tx: &mut Transaction,
+        slug: &str,
+        variant: Option<Uuid>,
+        amount: i32,
+    ) -> Result<(Product, Decimal)> {
+        let mut product = Product::read_for_update(tx, slug)?;
+        if amount <= 0 {
+            return Err(Error::builder()
+                .code(Status::BadRequest)
+                .description("A quantidade comprada deve ser positiva")
+                .build());
+        }
+        let price = match variant {
+            Some(id) => {
+                let mut variant = Variant::read_for_update(tx, &product, id)?;
+                variant.sell(tx, amount)?;
+                variant.price(&product)
+            }
+            None if Variant::exists(tx, &product.slug)? => {
+                return Err(Error::builder()
+                    .code(Status::BadRequest)
+                    .description(&format!("Escolha uma variante de '{}'", product.slug))
+                    .build())
+            }
+            None => product.price,
+        };
+        product.sell(tx, amount)?;
+        Ok((product, price))
+    }
+}
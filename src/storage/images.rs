@@ -0,0 +1,72 @@
+use crate::{Error, Result};
+
+use image::io::{Limits, Reader};
+use image::ImageFormat;
+use rocket::http::Status;
+use std::io::Cursor;
+
+/// Maior largura ou altura aceita, para não decodificar imagens gigantes
+const MAX_DIMENSION: u32 = 8192;
+
+/// Uma imagem validada, pronta para ser guardada
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ProcessedImage {
+    /// O arquivo enviado, sem alterações
+    pub content: Vec<u8>,
+    pub extension: &'static str,
+    /// Miniatura em PNG
+    pub thumbnail: Vec<u8>,
+}
+
+/// Verifica se o conteúdo é uma imagem PNG, JPEG, GIF ou WebP válida e gera uma miniatura que
+/// cabe num quadrado de lado `size`. O formato é detectado pelo conteúdo, e não pelo nome ou pelo
+/// tipo informados
+pub fn process(content: Vec<u8>, size: u32) -> Result<ProcessedImage> {
+    let format = image::guess_format(&content)
+        .ok()
+        .filter(|format| {
+            matches!(
+                format,
+                ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+            )
+        })
+        .ok_or_else(|| {
+            Error::builder()
+                .code(Status::UnsupportedMediaType)
+                .description("Formato de imagem não suportado, use PNG, JPEG, GIF ou WebP")
+                .build()
+        })?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = Reader::with_format(Cursor::new(&content), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| {
+        Error::builder()
+            .code(Status::BadRequest)
+            .description(&format!(
+                "Imagem inválida ou maior que {}x{} pixels",
+                MAX_DIMENSION, MAX_DIMENSION
+            ))
+            .source(Box::new(e))
+            .build()
+    })?;
+
+    // Imagens pequenas não são ampliadas
+    let image = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .write_to(&mut thumbnail, ImageFormat::Png)
+        .map_err(|e| Error::builder().source(Box::new(e)).build())?;
+
+    Ok(ProcessedImage {
+        content,
+        extension: format.extensions_str()[0],
+        thumbnail: thumbnail.into_inner(),
+    })
+}
@@ -0,0 +1,44 @@
+use crate::storage::Storage;
+use crate::Result;
+
+use rocket::tokio::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Armazenamento num diretório local
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+    /// URL base dos arquivos, sem a barra final
+    url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf, url: &str) -> LocalStorage {
+        LocalStorage {
+            root,
+            url: url.trim_end_matches('/').into(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, content).await?;
+        Ok(())
+    }
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, key)
+    }
+}
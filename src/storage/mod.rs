@@ -0,0 +1,132 @@
+pub mod images;
+pub use images::ProcessedImage;
+
+pub mod local;
+pub use local::LocalStorage;
+
+use crate::{Error, Result};
+
+use rocket::fairing::AdHoc;
+use rocket::fs::{FileServer, TempFile};
+use rocket::http::Status;
+use serde::Deserialize;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Um lugar onde guardar arquivos enviados, como as imagens de produtos e lojas
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Guarda um arquivo sob a chave (um caminho relativo, como `products/abc.png`)
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<()>;
+    /// Remove um arquivo. Remover um arquivo que não existe não é um erro
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// URL pública de um arquivo
+    fn url(&self, key: &str) -> String;
+}
+
+/// Uma imagem guardada e a sua miniatura
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct StoredImage {
+    pub url: String,
+    pub thumbnail: String,
+    /// Chaves da imagem e da miniatura no armazenamento, para removê-las depois
+    pub keys: Vec<String>,
+}
+
+/// Armazenamento em uso, gerenciado pelo rocket
+pub struct Uploads {
+    storage: Box<dyn Storage>,
+    /// Lado máximo das miniaturas, em pixels
+    thumbnail_size: u32,
+}
+
+impl Uploads {
+    pub fn new(storage: Box<dyn Storage>, thumbnail_size: u32) -> Uploads {
+        Uploads {
+            storage,
+            thumbnail_size,
+        }
+    }
+    /// Valida e guarda uma imagem enviada, junto com a miniatura, sob o prefixo (como `products`)
+    pub async fn store_image(&self, prefix: &str, file: &TempFile<'_>) -> Result<StoredImage> {
+        let path = file.path().ok_or_else(|| {
+            Error::builder()
+                .code(Status::BadRequest)
+                .description("Envie a imagem como um arquivo")
+                .build()
+        })?;
+        let content = rocket::tokio::fs::read(path).await?;
+        let size = self.thumbnail_size;
+        let image = rocket::tokio::task::spawn_blocking(move || images::process(content, size))
+            .await
+            .map_err(|e| Error::builder().source(Box::new(e)).build())??;
+
+        let name = Uuid::new_v4();
+        let key = format!("{}/{}.{}", prefix, name, image.extension);
+        let thumbnail_key = format!("{}/{}-thumbnail.png", prefix, name);
+        self.storage.put(&key, image.content).await?;
+        if let Err(e) = self.storage.put(&thumbnail_key, image.thumbnail).await {
+            self.storage.delete(&key).await.ok();
+            return Err(e);
+        }
+        Ok(StoredImage {
+            url: self.storage.url(&key),
+            thumbnail: self.storage.url(&thumbnail_key),
+            keys: vec![key, thumbnail_key],
+        })
+    }
+    /// Remove os arquivos dessas chaves, guardadas quando foram enviados. Falhas são ignoradas,
+    /// e apenas deixam arquivos órfãos
+    pub async fn discard(&self, keys: &[String]) {
+        for key in keys {
+            if let Err(e) = self.storage.delete(key).await {
+                rocket::warn!("Não foi possível remover '{}': {}", key, e);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    backend: String,
+    /// Diretório do armazenamento `local`
+    path: Option<PathBuf>,
+    /// URL base dos arquivos. Caso seja um caminho, como `/uploads`, o próprio backend os serve
+    url: Option<String>,
+    thumbnail_size: Option<u32>,
+}
+
+/// Lê a seção `storage` da configuração e gerencia o armazenamento escolhido
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Storage", |rocket| async {
+        let config: Config = match rocket.figment().extract_inner("storage") {
+            Ok(config) => config,
+            Err(e) => {
+                rocket::error!("Configuração de armazenamento inválida: {}", e);
+                return Err(rocket);
+            }
+        };
+        let url = config.url.unwrap_or_else(|| "/uploads".into());
+        let (storage, rocket): (Box<dyn Storage>, _) = match config.backend.as_str() {
+            "local" => {
+                let path = config.path.unwrap_or_else(|| "uploads".into());
+                if let Err(e) = std::fs::create_dir_all(&path) {
+                    rocket::error!("Não foi possível criar '{}': {}", path.display(), e);
+                    return Err(rocket);
+                }
+                let rocket = if url.starts_with('/') {
+                    rocket.mount(url.as_str(), FileServer::from(&path))
+                } else {
+                    rocket
+                };
+                (Box::new(LocalStorage::new(path, &url)), rocket)
+            }
+            other => {
+                rocket::error!("Armazenamento desconhecido: '{}'", other);
+                return Err(rocket);
+            }
+        };
+        let uploads = Uploads::new(storage, config.thumbnail_size.unwrap_or(320));
+        Ok(rocket.manage(uploads))
+    })
+}
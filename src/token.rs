@@ -0,0 +1,80 @@
+use crate::{Error, Result};
+
+use hmac::{Hmac, Mac, NewMac};
+use rocket::fairing::AdHoc;
+use sha2::Sha256;
+
+/// Chave secreta usada para guardar os tokens de sessão como hash (HMAC-SHA256). Assim, um
+/// vazamento da database não expõe as sessões abertas
+pub struct TokenKey(Vec<u8>);
+
+impl TokenKey {
+    pub fn new(key: &[u8]) -> Result<TokenKey> {
+        if key.len() < 32 {
+            return Err(Error::builder()
+                .description("A chave de tokens deve ter pelo menos 32 bytes")
+                .build());
+        }
+        Ok(TokenKey(key.into()))
+    }
+    /// Hash (em hexadecimal) de um token
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.0).expect("HMAC aceita qualquer chave");
+        mac.update(token.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Lê a chave `token_key` da configuração e a gerencia
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Token Key", |rocket| async {
+        let key: String = match rocket.figment().extract_inner("token_key") {
+            Ok(key) => key,
+            Err(e) => {
+                rocket::error!("Chave de tokens inválida: {}", e);
+                return Err(rocket);
+            }
+        };
+        match TokenKey::new(key.as_bytes()) {
+            Ok(key) => Ok(rocket.manage(key)),
+            Err(e) => {
+                rocket::error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_short_keys() {
+        assert!(TokenKey::new(&[0; 31]).is_err());
+        assert!(TokenKey::new(&[0; 32]).is_ok());
+    }
+
+    #[test]
+    fn hashes_with_hmac_sha256() {
+        // RFC 4231, caso de teste 6
+        let key = TokenKey::new(&[0xaa; 131]).unwrap();
+        assert_eq!(
+            key.hash("Test Using Larger Than Block-Size Key - Hash Key First"),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        let key = TokenKey::new(&[1; 32]).unwrap();
+        let other = TokenKey::new(&[2; 32]).unwrap();
+        assert_eq!(key.hash("token"), key.hash("token"));
+        assert_ne!(key.hash("token"), other.hash("token"));
+        assert_ne!(key.hash("token"), key.hash("token2"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Senhas de uso único baseadas em tempo (TOTP, RFC 6238), como as dos apps autenticadores
+use crate::csrf::constant_time_eq;
+
+use base32::Alphabet;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, Rng};
+use rocket::http::RawStr;
+use sha1::Sha1;
+
+/// Duração de cada código, em segundos
+pub const PERIOD: u64 = 30;
+/// Quantidade de dígitos de cada código
+pub const DIGITS: u32 = 6;
+/// Nome mostrado nos apps autenticadores
+pub const ISSUER: &str = "Cincobola";
+
+const ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// Gera um segredo aleatório de 160 bits, em base32
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill(&mut secret);
+    base32::encode(ALPHABET, &secret)
+}
+
+/// URI `otpauth://` para cadastrar o segredo num app autenticador (geralmente por QR code)
+pub fn uri(secret: &str, account: &str) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        RawStr::new(&label).percent_encode(),
+        secret,
+        RawStr::new(ISSUER).percent_encode(),
+        DIGITS,
+        PERIOD
+    )
+}
+
+/// Intervalo de tempo correspondente a um timestamp unix
+pub fn step(timestamp: u64) -> u64 {
+    timestamp / PERIOD
+}
+
+/// Código de um intervalo (HOTP, RFC 4226)
+pub fn code(secret: &str, step: u64) -> Option<String> {
+    let secret = base32::decode(ALPHABET, secret)?;
+    let mut mac = Hmac::<Sha1>::new_varkey(&secret).ok()?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+/// Procura o intervalo (o atual ou um vizinho, tolerando relógios levemente dessincronizados)
+/// cujo código é o dado. Os códigos são comparados em tempo constante
+pub fn verify(secret: &str, code: &str, timestamp: u64) -> Option<u64> {
+    let current = step(timestamp);
+    let code = code.trim().replace(' ', "");
+    (current.saturating_sub(1)..=current + 1)
+        .filter(|&step| {
+            self::code(secret, step)
+                .is_some_and(|expected| constant_time_eq(expected.as_bytes(), code.as_bytes()))
+        })
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Segredo dos vetores de teste da RFC 6238 (SHA-1): "12345678901234567890"
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    /// Vetores da RFC 6238, apêndice B. Os códigos de lá têm 8 dígitos; os de 6 são os finais
+    const VECTORS: &[(u64, &str)] = &[
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    #[test]
+    fn rfc_6238_vectors() {
+        for &(timestamp, expected) in VECTORS {
+            assert_eq!(code(SECRET, step(timestamp)).as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn verify_tolerates_one_step() {
+        let (timestamp, expected) = VECTORS[3];
+        let current = step(timestamp);
+        assert_eq!(verify(SECRET, expected, timestamp), Some(current));
+        assert_eq!(verify(SECRET, expected, timestamp + PERIOD), Some(current));
+        assert_eq!(verify(SECRET, expected, timestamp - PERIOD), Some(current));
+        assert_eq!(verify(SECRET, expected, timestamp + 2 * PERIOD), None);
+    }
+
+    #[test]
+    fn verify_ignores_spaces() {
+        assert_eq!(
+            verify(SECRET, " 005 924 ", 1234567890),
+            Some(step(1234567890))
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_codes_and_secrets() {
+        assert_eq!(verify(SECRET, "005925", 1234567890), None);
+        assert_eq!(verify(SECRET, "", 1234567890), None);
+        assert_eq!(verify("not base32!", "005924", 1234567890), None);
+    }
+
+    #[test]
+    fn generated_secrets_are_valid() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert!(code(&secret, 0).is_some());
+    }
+
+    #[test]
+    fn uri_has_the_label_and_parameters() {
+        assert_eq!(
+            uri(SECRET, "fulano@exemplo.com"),
+            "otpauth://totp/Cincobola:fulano@exemplo.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+            &issuer=Cincobola&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}